@@ -1,12 +1,22 @@
 // [[file:~/src/roper2/src/emu/hatchery.org::hatch][hatch]]
-use crate::emu::loader::{get_mode, read_pc, uc_general_registers, Engine};
+use crate::emu::loader::{
+    align_stack_entry, get_mode, msr_id, read_pc, register_id, snapshot_sp, uc_general_registers,
+    warn_unknown_register, Arch, BranchKind, Engine,
+};
 use crate::gen;
-use crate::gen::phenotype::{VisitRecord, WriteRecord};
+use crate::gen::genotype::{pack_words, unpack_word, unpack_words};
+use crate::gen::phenotype::{
+    Access, BranchRecord, CrashKind, FaultRecord, ModeSwitch, ReadRecord, SyscallRecord,
+    Termination, VisitRecord, WriteRecord,
+};
+use crate::par::counters;
 use crate::par::statics::*;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
 use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant};
 /* An expect of 0 will cause this loop to run indefinitely */
 pub fn spawn_hatchery(
     num_engines: usize,
@@ -48,14 +58,16 @@ pub fn spawn_hatchery(
              */
             if incoming.has_hatched() {
                 num_already_hatched += 1;
+                counters::bump(&counters::ALREADY_HATCHED);
                 already_hatched_tx.send(incoming).unwrap();
             } else {
                 carousel_tx.send(incoming).unwrap();
                 counter += 1;
+                counters::bump(&counters::DISPATCHED);
             }
             coop = (coop + 1) % carousel.len();
             if (counter + num_already_hatched) % 100000 == 0 {
-                println!(
+                crate::notice!(
                     "[{} Emulations; num_already_hatched = {}; ratio new: {}]",
                     counter,
                     num_already_hatched,
@@ -67,7 +79,7 @@ pub fn spawn_hatchery(
         /* clean up the carousel */
         while !carousel.is_empty() {
             if let Some((tx, h)) = carousel.pop() {
-                println!(")-- cleaning up {:?} --(", tx);
+                crate::notice!(")-- cleaning up {:?} --(", tx);
                 drop(tx.to_owned());
                 h.join().unwrap();
             };
@@ -77,30 +89,51 @@ pub fn spawn_hatchery(
     (into_hatch_tx, from_hatch_rx, handle)
 }
 fn spawn_coop(rx: Receiver<gen::Creature>, tx: SyncSender<gen::Creature>) {
-    /* a thread-local emulator */
-    let mut emu = Engine::new(*ARCHITECTURE);
+    /* thread-local emulators, one for each slide of the image */
+    let mut engines = Engines::new();
 
     /* Hatch each incoming creature as it arrives, and send the creature
      * back to the caller of spawn_hatchery. */
     for incoming in rx {
         let mut creature = incoming;
-        let phenome = hatch_cases(&mut creature, &mut emu);
+        let phenome = hatch_cases(&mut creature, &mut engines);
         creature.phenome = phenome;
+        counters::bump(&counters::HATCHED);
         if !creature.has_hatched() {
+            crate::log::restore_tty();
             println!("[in spawn_coop] This bastard hasn't hatched!\n{}", creature);
-            std::process::exit(1);
+            crate::log::shutdown(1);
         }
+        counters::bump(&counters::EMULATED);
         tx.send(creature).unwrap(); /* goes back to the thread that called spawn_hatchery */
     }
 }
+/* Engines are built as the slides they're needed for turn up, since
+ * the slides are only known once the problems are posed. */
+pub type Engines<'a> = HashMap<u64, Engine<'a>>;
+
 #[inline]
-pub fn hatch_cases(creature: &mut gen::Creature, emu: &mut Engine) -> gen::Phenome {
+pub fn hatch_cases(creature: &mut gen::Creature, engines: &mut Engines) -> gen::Phenome {
     let mut map = gen::Phenome::new();
     {
         let mut inputs: Vec<gen::Input> = creature.phenome.keys().cloned().collect();
         assert!(!inputs.is_empty());
         while !inputs.is_empty() {
             let input = inputs.pop().unwrap();
+            let (_, slide) = split_slide(&input);
+            if !engines.contains_key(&slide) {
+                match Engine::with_slide(*ARCHITECTURE, slide) {
+                    Ok(emu) => {
+                        engines.insert(slide, emu);
+                    }
+                    Err(e) => {
+                        crate::notice!("[!] No engine for slide 0x{:x}: {:?}", slide, e);
+                        map.insert(input.to_vec(), None);
+                        continue;
+                    }
+                }
+            };
+            let emu = engines.get_mut(&slide).unwrap();
             /* This can't really be threaded, due to the unsendability of emu */
             let pod = hatch(creature, &input, emu);
             map.insert(input.to_vec(), Some(pod));
@@ -111,26 +144,66 @@ pub fn hatch_cases(creature: &mut gen::Creature, emu: &mut Engine) -> gen::Pheno
 #[inline]
 pub fn hatch(creature: &mut gen::Creature, input: &gen::Input, emu: &mut Engine) -> gen::Pod {
     let mut payload = creature.genome.pack(input);
-    let start_addr = creature.genome.entry().unwrap();
+    /* Under ASLR, the gadget words are packed with the slide added, and
+     * so is the first address, which the chain starts out at. */
+    let start_addr = creature.genome.start_addr().unwrap() + emu.slide;
     /* A missing entry point should be considered an error,
      * since we try to guard against this in our generation
      * functions.
      */
+    let setup = emulation_setup(input);
+    let below = pack_words(&setup.stack_below);
+    let above = pack_words(&setup.stack_above);
     let (stack_addr, stack_size) = emu.find_stack();
-    payload.truncate(stack_size / 2);
-    let _payload_len = payload.len();
-    let stack_entry = stack_addr + (stack_size / 2) as u64;
+    let stack_end = stack_addr + stack_size as u64;
+    /* chains run from a snapshot's own sp, on its own stack, if it has
+     * one, and otherwise from the middle of a fresh one */
+    let sp = match snapshot_sp() {
+        Some(sp) if *SNAPSHOT_STACK => sp,
+        _ => align_stack_entry(stack_addr + (stack_size / 2) as u64) + *ADDR_WIDTH as u64,
+    };
+    let payload_addr = (sp as i64 + *PAYLOAD_OFFSET) as u64;
+    /* Whatever doesn't fit is cut off, but the chain pays for it. */
+    let room = stack_end.saturating_sub(payload_addr.saturating_add(above.len() as u64)) as usize;
+    let mut payload_overflow = payload.len().saturating_sub(room);
+    payload.truncate(room);
     emu.restore_state().unwrap();
 
-    /* load payload **/
-    emu.mem_write(stack_entry, &payload)
-        .expect("mem_write fail in hatch");
-    emu.set_sp(stack_entry + *ADDR_WIDTH as u64).unwrap();
+    for (name, value) in setup.registers.iter() {
+        match (register_id(emu.arch, name), msr_id(emu.arch, name)) {
+            (Some(regid), _) => emu.uc.reg_write(regid, *value).unwrap(),
+            (None, Some(msr)) => emu.write_msr(msr, *value).unwrap(),
+            (None, None) => warn_unknown_register(name, emu.arch),
+        }
+    }
+    for (addr, bytes) in setup.preload.iter() {
+        emu.mem_write(*addr, bytes)
+            .expect("mem_write fail preloading memory in hatch");
+    }
+
+    /* load payload, with the configured stack words on either side, as
+     * much of each as lands on the stack **/
+    let regions = [
+        (payload_addr.wrapping_sub(below.len() as u64), &below[..]),
+        (payload_addr, &payload[..]),
+        (payload_addr.wrapping_add(payload.len() as u64), &above[..]),
+    ];
+    for (addr, bytes) in regions.iter() {
+        let (addr, bytes, lost) = clamp_to_range(*addr, bytes, stack_addr, stack_end);
+        payload_overflow += lost;
+        if !bytes.is_empty() {
+            emu.mem_write(addr, bytes).expect("mem_write fail in hatch");
+        };
+    }
+    emu.set_sp(sp).unwrap();
 
     let visitor: Rc<RefCell<Vec<VisitRecord>>> = Rc::new(RefCell::new(Vec::new()));
     let writelog = Rc::new(RefCell::new(Vec::new()));
     let retlog = Rc::new(RefCell::new(Vec::new()));
     let jmplog = Rc::new(RefCell::new(Vec::new()));
+    let syscalls = Rc::new(RefCell::new(Vec::new()));
+    let branchlog = Rc::new(RefCell::new(Vec::new()));
+    let mode_switches = Rc::new(RefCell::new(Vec::new()));
 
     let mem_write_hook = {
         let writelog = writelog.clone();
@@ -153,11 +226,59 @@ pub fn hatch(creature: &mut gen::Creature, input: &gen::Input, emu: &mut Engine)
         emu.hook_writeable_mem(callback)
     };
 
+    let readlog = Rc::new(RefCell::new(Vec::new()));
+    let mem_read_hooks = if *LOG_READS {
+        let readlog = readlog.clone();
+        let callback = move |uc: &unicorn::Unicorn,
+                             _memtype: unicorn::MemType,
+                             addr: u64,
+                             size: usize,
+                             _val: i64| {
+            /* the hook fires before the read, so we look for ourselves */
+            let mut buf = [0u8; 8];
+            let size = usize::min(size, buf.len());
+            if uc.mem_read(addr, &mut buf[..size]).is_ok() {
+                let pc = read_pc(uc).unwrap();
+                readlog.borrow_mut().push(ReadRecord {
+                    pc,
+                    src_addr: addr,
+                    value: unpack_word(&buf[..size]),
+                    size,
+                });
+            };
+            true
+        };
+        /* the ranges are given against the static image, so they move
+         * with whatever they fall in */
+        let ranges = READ_RANGES
+            .iter()
+            .map(|&(begin, end)| {
+                let delta = emu.slide_addr(begin).wrapping_sub(begin);
+                (begin.wrapping_add(delta), end.wrapping_add(delta))
+            })
+            .collect::<Vec<(u64, u64)>>();
+        emu.hook_mem_reads(&ranges, callback)
+    } else {
+        Ok(Vec::new())
+    };
+
     let visit_hook = {
         let visitor = visitor.clone();
+        let mode_switches = mode_switches.clone();
         let callback = move |uc: &unicorn::Unicorn, addr: u64, size: u32| {
             let mut vmut = visitor.borrow_mut();
+            /* the CPU's own idea of the mode, so that we follow it through
+             * any bx, blx or pop {pc} that lands on an odd address */
             let mode = get_mode(&uc);
+            if let Some(last) = vmut.last() {
+                if last.mode != mode {
+                    mode_switches.borrow_mut().push(ModeSwitch {
+                        src: last.pc,
+                        dst: addr,
+                        mode,
+                    });
+                }
+            };
             let size: usize = (size & 0xF) as usize;
             let registers = uc_general_registers(&uc).unwrap();
             let visit_record = VisitRecord {
@@ -171,26 +292,81 @@ pub fn hatch(creature: &mut gen::Creature, input: &gen::Input, emu: &mut Engine)
         emu.hook_exec_mem(callback)
     };
 
-    let ret_hook = {
-        let retlog = retlog.clone();
-        let callback = move |_uc: &unicorn::Unicorn, addr: u64, _size: u32| {
-            let mut retlog = retlog.borrow_mut();
-            let pc = addr;
-            retlog.push(pc);
+    let stopped_at_syscall = Rc::new(Cell::new(false));
+    let syscall_hook = {
+        let syscalls = syscalls.clone();
+        let stopped_at_syscall = stopped_at_syscall.clone();
+        let callback = move |uc: &unicorn::Unicorn, pc: u64, number: u64, args: Vec<u64>| {
+            syscalls
+                .borrow_mut()
+                .push(SyscallRecord { pc, number, args });
+            if *STOP_AT_SYSCALL {
+                stopped_at_syscall.set(true);
+                uc.emu_stop().unwrap();
+            }
         };
-        emu.hook_rets(callback)
+        emu.hook_syscalls(callback)
     };
 
-    let indirect_jump_hook = {
+    let branch_hook = {
+        let retlog = retlog.clone();
+        let on_ret = move |_uc: &unicorn::Unicorn, addr: u64, _size: u32| {
+            retlog.borrow_mut().push(addr);
+        };
         let newjmplog = jmplog;
-        let callback = move |_uc: &unicorn::Unicorn, addr: u64, _size: u32| {
-            let mut jmplog = newjmplog.borrow_mut();
-            jmplog.push(addr);
+        let on_indirect_jump = move |_uc: &unicorn::Unicorn, addr: u64, _size: u32| {
+            newjmplog.borrow_mut().push(addr);
+        };
+        let branchlog = branchlog.clone();
+        let on_transfer = move |_uc: &unicorn::Unicorn, src: u64, dst: u64, kind: BranchKind| {
+            branchlog.borrow_mut().push(BranchRecord { src, dst, kind });
+        };
+        emu.hook_branches(on_ret, on_indirect_jump, on_transfer)
+    };
+
+    /* the pc, address, size and kind of the faulting access, as they were
+     * in the slid image; it's classified once emulation is over */
+    let fault = Rc::new(Cell::new(None));
+    let fault_hook = {
+        let fault = fault.clone();
+        let callback = move |uc: &unicorn::Unicorn,
+                             memtype: unicorn::MemType,
+                             addr: u64,
+                             size: usize,
+                             _val: i64| {
+            let (access, unmapped) = match memtype {
+                unicorn::MemType::READ_UNMAPPED => (Access::Read, true),
+                unicorn::MemType::WRITE_UNMAPPED => (Access::Write, true),
+                unicorn::MemType::FETCH_UNMAPPED => (Access::Fetch, true),
+                unicorn::MemType::READ_PROT => (Access::Read, false),
+                unicorn::MemType::WRITE_PROT => (Access::Write, false),
+                _ => (Access::Fetch, false),
+            };
+            fault.set(Some((
+                read_pc(uc).unwrap_or(addr),
+                addr,
+                size,
+                access,
+                unmapped,
+            )));
+            false
         };
-        emu.hook_indirect_jumps(callback)
+        emu.hook_mem_faults(callback)
     };
 
-    let _res = emu.start(start_addr, 0, 0, 1024);
+    let until = TARGET_ADDRESS.map_or(0, |target| emu.slide_addr(target));
+    let started = Instant::now();
+    let res = emu.start(start_addr, until, *EMULATION_TIMEOUT, *INSTRUCTION_BUDGET);
+    let elapsed = started.elapsed();
+    let stop_pc = read_pc(&emu.uc).map(|pc| emu.unslide(pc)).unwrap_or(0);
+
+    /* A branch to somewhere unexecutable never reaches its destination,
+     * but the pc tells us where it was headed. */
+    if let Some((src, kind)) = emu.take_pending_branch() {
+        if let Ok(dst) = read_pc(&emu.uc) {
+            branchlog.borrow_mut().push(BranchRecord { src, dst, kind });
+        }
+    };
 
     /* Now, clean up the hooks */
     match visit_hook {
@@ -198,7 +374,7 @@ pub fn hatch(creature: &mut gen::Creature, input: &gen::Input, emu: &mut Engine)
             emu.remove_hook(h).unwrap();
         }
         Err(e) => {
-            println!("visit_hook didn't take {:?}", e);
+            crate::notice!("visit_hook didn't take {:?}", e);
         }
     }
     match mem_write_hook {
@@ -206,23 +382,44 @@ pub fn hatch(creature: &mut gen::Creature, input: &gen::Input, emu: &mut Engine)
             emu.remove_hook(h).unwrap();
         }
         Err(e) => {
-            println!("mem_write_hook didn't take {:?}", e);
+            crate::notice!("mem_write_hook didn't take {:?}", e);
         }
     }
-    match ret_hook {
+
+    match branch_hook {
         Ok(h) => {
             emu.remove_hook(h).unwrap();
         }
         Err(e) => {
-            println!("ret_hook didn't take: {:?}", e);
+            crate::notice!("branch_hook didn't take: {:?}", e);
+        }
+    }
+    match mem_read_hooks {
+        Ok(hs) => {
+            for h in hs {
+                emu.remove_hook(h).unwrap();
+            }
+        }
+        Err(e) => {
+            crate::notice!("mem_read_hooks didn't take: {:?}", e);
         }
     }
-    match indirect_jump_hook {
+    match fault_hook {
         Ok(h) => {
             emu.remove_hook(h).unwrap();
         }
         Err(e) => {
-            println!("indirect_jmp_hook didn't take: {:?}", e);
+            crate::notice!("fault_hook didn't take: {:?}", e);
+        }
+    }
+    match syscall_hook {
+        Ok(hs) => {
+            for h in hs {
+                emu.remove_hook(h).unwrap();
+            }
+        }
+        Err(e) => {
+            crate::notice!("syscall_hook didn't take: {:?}", e);
         }
     }
 
@@ -235,6 +432,313 @@ pub fn hatch(creature: &mut gen::Creature, input: &gen::Input, emu: &mut Engine)
     let rtmp = retlog;
     let retlog = rtmp.borrow().to_vec();
 
-    gen::Pod::new(registers, visited, writelog, retlog)
+    /* Addresses are reported as they'd be in the static image, so that
+     * they can be disassembled and compared across slides. */
+    let visited: Vec<VisitRecord> = visited
+        .into_iter()
+        .map(|v| VisitRecord {
+            pc: emu.unslide(v.pc),
+            ..v
+        })
+        .collect();
+    let writelog = writelog
+        .into_iter()
+        .map(|w| WriteRecord {
+            pc: emu.unslide(w.pc),
+            ..w
+        })
+        .collect();
+    let retlog = retlog.into_iter().map(|r| emu.unslide(r)).collect();
+    let termination = termination(
+        res,
+        stop_pc,
+        visited.len(),
+        elapsed,
+        stopped_at_syscall.get(),
+    );
+    let mut pod = gen::Pod::new(registers, visited, writelog, retlog);
+    pod.termination = termination;
+    /* The payload's gadget words were slid, so they're compared with the
+     * fault address as they'd be in the static image. */
+    let payload_words = unpack_words(&payload)
+        .into_iter()
+        .map(|w| emu.unslide(w))
+        .collect::<Vec<u64>>();
+    pod.fault = fault.get().map(|(pc, addr, size, access, unmapped)| {
+        let addr = emu.unslide(addr);
+        FaultRecord {
+            pc: emu.unslide(pc),
+            addr,
+            size,
+            access,
+            unmapped,
+            kind: classify_fault(access, addr, &payload_words),
+        }
+    });
+    /* the sentinel is never mapped, so returning to it faults, but that's
+     * the chain getting where it was meant to, not a crash */
+    if !termination.is_crash() {
+        pod.fault = None;
+    };
+    pod.payload_overflow = payload_overflow;
+    pod.readlog = readlog
+        .borrow()
+        .iter()
+        .map(|r: &ReadRecord| ReadRecord {
+            pc: emu.unslide(r.pc),
+            src_addr: emu.unslide(r.src_addr),
+            ..r.clone()
+        })
+        .collect();
+    pod.syscalls = syscalls
+        .borrow()
+        .iter()
+        .map(|s| SyscallRecord {
+            pc: emu.unslide(s.pc),
+            ..s.clone()
+        })
+        .collect();
+    pod.branchlog = branchlog
+        .borrow()
+        .iter()
+        .map(|b| BranchRecord {
+            src: emu.unslide(b.src),
+            dst: emu.unslide(b.dst),
+            ..b.clone()
+        })
+        .collect();
+    pod.mode_switches = mode_switches
+        .borrow()
+        .iter()
+        .map(|m| ModeSwitch {
+            src: emu.unslide(m.src),
+            dst: emu.unslide(m.dst),
+            ..m.clone()
+        })
+        .collect();
+    pod
 }
 // hatch ends here
+
+/* Whether the pc is at addr, allowing for the Thumb bit on ARM. */
+fn pc_at(pc: u64, addr: u64) -> bool {
+    match *ARCHITECTURE {
+        Arch::Arm(_) => pc == addr & !1,
+        _ => pc == addr,
+    }
+}
+
+/* how far from a word of the payload an access can land and still count
+ * as aimed by it, allowing for displacements like [rax+0x10] */
+const CONTROL_SLACK: u64 = 0x100;
+
+/* Sorts a memory fault by whether the payload chose where it happened. */
+fn classify_fault(access: Access, addr: u64, payload_words: &[u64]) -> CrashKind {
+    let aimed =
+        |w: &u64| addr.wrapping_sub(*w) < CONTROL_SLACK || w.wrapping_sub(addr) < CONTROL_SLACK;
+    match access {
+        Access::Fetch if payload_words.iter().any(|w| pc_at(addr, *w)) => CrashKind::ControlledPc,
+        _ if addr < 0x1000 => CrashKind::NullDeref,
+        Access::Write if payload_words.iter().any(aimed) => CrashKind::ControlledWrite,
+        Access::Read if payload_words.iter().any(aimed) => CrashKind::ControlledRead,
+        _ => CrashKind::Wild,
+    }
+}
+
+/* Cuts down bytes meant for addr to the part that falls in [lo, hi),
+ * returning where that part goes, and how many bytes were cut. */
+fn clamp_to_range(addr: u64, bytes: &[u8], lo: u64, hi: u64) -> (u64, &[u8], usize) {
+    let end = addr.saturating_add(bytes.len() as u64);
+    let (start, stop) = (u64::max(addr, lo), u64::min(end, hi));
+    if start >= stop {
+        return (lo, &[], bytes.len());
+    };
+    let kept = &bytes[(start - addr) as usize..(stop - addr) as usize];
+    (start, kept, bytes.len() - kept.len())
+}
+
+/* Works out why the emulator stopped, from what emu_start returned, where
+ * it left the pc, how many instructions it got through, and how long it
+ * took about it. A clean stop is only put down to the budget or the
+ * clock if the one was spent or the other ran out. */
+fn termination(
+    res: Result<(), unicorn::Error>,
+    pc: u64,
+    executed: usize,
+    elapsed: Duration,
+    stopped_at_syscall: bool,
+) -> Termination {
+    if stopped_at_syscall {
+        return Termination::Syscall;
+    };
+    if TARGET_ADDRESS.map_or(false, |target| pc_at(pc, target)) {
+        return Termination::ReachedTarget;
+    };
+    /* the sentinel is never mapped, so the fetch from it fails */
+    if SENTINEL_ADDRESS.map_or(false, |sentinel| pc_at(pc, sentinel)) {
+        return Termination::ReturnedToSentinel;
+    };
+    match res {
+        Ok(()) if executed >= *INSTRUCTION_BUDGET => Termination::BudgetExhausted,
+        Ok(())
+            if *EMULATION_TIMEOUT > 0 && elapsed >= Duration::from_micros(*EMULATION_TIMEOUT) =>
+        {
+            Termination::TimedOut
+        }
+        Ok(()) => Termination::Halted,
+        Err(unicorn::Error::READ_UNMAPPED) => Termination::UnmappedRead,
+        Err(unicorn::Error::WRITE_UNMAPPED) => Termination::UnmappedWrite,
+        Err(unicorn::Error::FETCH_UNMAPPED) => Termination::UnmappedFetch,
+        Err(unicorn::Error::READ_PROT)
+        | Err(unicorn::Error::WRITE_PROT)
+        | Err(unicorn::Error::FETCH_PROT) => Termination::ProtectionFault,
+        Err(unicorn::Error::INSN_INVALID) => Termination::InvalidInstruction,
+        Err(_) => Termination::Error,
+    }
+}
+
+#[test]
+fn test_clamp_to_range() {
+    let bytes = [1, 2, 3, 4, 5, 6, 7, 8];
+    assert_eq!(
+        clamp_to_range(0x1000, &bytes, 0x1000, 0x2000),
+        (0x1000, &bytes[..], 0)
+    );
+    assert_eq!(
+        clamp_to_range(0xffc, &bytes, 0x1000, 0x2000),
+        (0x1000, &bytes[4..], 4)
+    );
+    assert_eq!(
+        clamp_to_range(0x1ffe, &bytes, 0x1000, 0x2000),
+        (0x1ffe, &bytes[..2], 6)
+    );
+    assert_eq!(clamp_to_range(0x3000, &bytes, 0x1000, 0x2000).2, 8);
+}
+
+#[test]
+fn test_termination() {
+    let quick = Duration::from_micros(1);
+    assert_eq!(
+        termination(Ok(()), 0x1000, *INSTRUCTION_BUDGET, quick, false),
+        Termination::BudgetExhausted
+    );
+    assert_eq!(
+        termination(
+            Err(unicorn::Error::FETCH_UNMAPPED),
+            0x41414141,
+            3,
+            quick,
+            false
+        ),
+        Termination::UnmappedFetch
+    );
+    assert_eq!(
+        termination(Err(unicorn::Error::INSN_INVALID), 0x1000, 3, quick, false),
+        Termination::InvalidInstruction
+    );
+    assert_eq!(
+        termination(Ok(()), 0x1000, 3, quick, true),
+        Termination::Syscall
+    );
+    /* stopped short of the budget, well before any timeout */
+    assert_eq!(
+        termination(Ok(()), 0x1000, 3, quick, false),
+        Termination::Halted
+    );
+}
+
+#[test]
+fn test_classify_fault() {
+    let words = [0x41414140, 0x601000];
+    assert_eq!(
+        classify_fault(Access::Fetch, 0x41414140, &words),
+        CrashKind::ControlledPc
+    );
+    assert_eq!(
+        classify_fault(Access::Write, 0x601010, &words),
+        CrashKind::ControlledWrite
+    );
+    assert_eq!(
+        classify_fault(Access::Read, 0x8, &words),
+        CrashKind::NullDeref
+    );
+    assert_eq!(
+        classify_fault(Access::Read, 0xdead0000, &words),
+        CrashKind::Wild
+    );
+}
+
+/* Runs against tests/fixtures/i386-gadgets, a bare i386 executable with
+ * one PT_LOAD segment at 0x08048000 holding, from 0x08048054:
+ *   pop eax; ret / pop ebx; ret / int 0x80; ret
+ * Since that has to be the binary under study, it's ignored by default,
+ * and run with
+ *   ROPER_BINARY=tests/fixtures/i386-gadgets cargo test test_hatch_i386 -- --ignored */
+#[test]
+#[ignore]
+fn test_hatch_i386() {
+    use crate::emu::loader::Mode;
+    use crate::gen::{Allele, Chain, Creature, Gadget, Metadata};
+    assert!(
+        *ARCHITECTURE == Arch::X86(Mode::Bits32) && BINARY_PATH.ends_with("i386-gadgets"),
+        "test_hatch_i386 needs ROPER_BINARY=tests/fixtures/i386-gadgets, not {}",
+        *BINARY_PATH
+    );
+    let gadget = |entry: u64, ret_addr: u64| {
+        Allele::Gadget(Gadget {
+            ret_addr,
+            entry,
+            sp_delta: 2,
+            mode: Mode::Bits32,
+        })
+    };
+    let chain = Chain {
+        alleles: vec![
+            gadget(0x08048054, 0x08048055),
+            Allele::Input(0),
+            gadget(0x08048056, 0x08048057),
+            Allele::Input(1),
+            gadget(0x08048058, 0x0804805a),
+        ],
+        metadata: Metadata::new(),
+        xbits: 0,
+        generation: 0,
+    };
+    let mut creature = Creature::new(chain, 0);
+    /* exit(42), with ASLR off */
+    let input = vec![1, 42];
+    let mut emu = Engine::new(*ARCHITECTURE);
+    let pod = hatch(&mut creature, &input, &mut emu);
+    assert_eq!(&pod.retlog[..2], &[0x08048055, 0x08048057]);
+    assert_eq!(pod.syscalls.len(), 1);
+    assert_eq!(pod.syscalls[0].pc, 0x08048058);
+    assert_eq!(pod.syscalls[0].number, 1);
+    assert_eq!(pod.syscalls[0].args[0], 42);
+    if *STOP_AT_SYSCALL {
+        assert_eq!(pod.termination, Termination::Syscall);
+        assert_eq!(pod.retlog.len(), 2);
+    } else {
+        assert_eq!(pod.retlog.len(), 3);
+    }
+}
+
+#[test]
+fn test_sentinel_return_is_no_crash() {
+    use crate::gen::FitFuncs;
+    let mut pod = gen::Pod::new(Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    pod.termination = Termination::ReturnedToSentinel;
+    pod.fault = Some(FaultRecord {
+        pc: 0xdead0000,
+        addr: 0xdead0000,
+        size: 4,
+        access: Access::Fetch,
+        unmapped: true,
+        kind: CrashKind::Wild,
+    });
+    let mut phenome = gen::Phenome::new();
+    phenome.insert(vec![1], Some(pod.clone()));
+    assert_eq!(phenome.fault_rate(|_| true), 0.0);
+    pod.termination = Termination::UnmappedFetch;
+    phenome.insert(vec![1], Some(pod));
+    assert_eq!(phenome.fault_rate(|_| true), 1.0);
+}
@@ -1,41 +1,139 @@
+use crate::log::disas;
 use crate::par::statics::*;
 use crate::unicorn::*;
-use goblin::{elf, Object};
+use goblin::mach::{Mach, MachO};
+use goblin::pe::section_table::{IMAGE_SCN_MEM_EXECUTE, IMAGE_SCN_MEM_READ, IMAGE_SCN_MEM_WRITE};
+use goblin::{elf, mach, pe, Object};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Mutex;
 
 pub struct Engine<'a> {
     pub uc: Box<unicorn::Unicorn<'a>>,
     pub arch: Arch,
     pub regids: Vec<i32>,
     mem: MemImage<'a>,
+    /* how far everything but the stack has been moved from MEM_IMAGE */
+    pub slide: u64,
     writeable_bak: Option<MemImage<'a>>,
+    /* pages written to since the last save or restore */
+    dirty: Rc<RefCell<HashSet<u64>>>,
     default_uc_mode: unicorn::Mode,
     saved_context: unicorn::Context,
+    pending_branch: Rc<Cell<Option<(u64, BranchKind)>>>,
+    branch_kinds: Rc<BranchKindCache>,
 }
 
 impl<'a> Engine<'a> {
     pub fn new(arch: Arch) -> Self {
-        let (_uc_arch, uc_mode) = arch.as_uc();
-        let (emu, mem) = init_emulator(arch, false).unwrap();
+        Engine::with_slide(arch, 0).unwrap()
+    }
+
+    /// An engine with the loaded files mapped `slide` bytes above where
+    /// MEM_IMAGE has them, and relocated to match, as if loaded under ASLR.
+    pub fn with_slide(arch: Arch, slide: u64) -> Result<Self, unicorn::Error> {
+        let (_uc_arch, uc_mode) = arch.as_uc()?;
+        let (emu, mem) = init_emulator(arch, false, slide)?;
         let regids = match arch {
             Arch::Arm(_) => regids(&ARM_REGISTERS),
+            Arch::Arm64(_) => regids(&ARM64_REGISTERS),
             Arch::Mips(_) => regids(&MIPS_REGISTERS),
             Arch::X86(Mode::Bits64) => regids(&X86_64_REGISTERS),
             Arch::X86(Mode::Bits32) => regids(&X86_32_REGISTERS),
-            _ => unreachable!("Not implemented"),
+            _ => return Err(unicorn::Error::ARCH),
         };
+        let branch_kinds = Rc::new(BranchKindCache::new(&mem));
         let mut emu = Engine {
             uc: emu,
             arch,
             mem,
+            slide,
             regids,
             default_uc_mode: uc_mode,
             saved_context: unicorn::Context::new(),
             writeable_bak: None,
+            dirty: Rc::new(RefCell::new(HashSet::new())),
+            pending_branch: Rc::new(Cell::new(None)),
+            branch_kinds,
+        };
+        /* the segment bases go in last, so that loading a selector
+         * can't clobber them */
+        let mut msrs = Vec::new();
+        for (name, value) in initial_registers() {
+            match (register_id(arch, &name), msr_id(arch, &name)) {
+                (Some(regid), _) => emu.uc.reg_write(regid, value).unwrap(),
+                (None, Some(msr)) => msrs.push((msr, value)),
+                (None, None) => warn_unknown_register(&name, arch),
+            }
+        }
+        for (msr, value) in msrs {
+            emu.write_msr(msr, value)?;
+        }
+        emu.track_dirty_pages()?;
+        emu.save_state()?;
+        Ok(emu)
+    }
+
+    /* The bindings only take plain register values, and a model specific
+     * register has to be passed to unicorn as an (id, value) struct, so
+     * we have the CPU write it for us, with a wrmsr on a scratch page
+     * that's mapped just for the purpose. The registers wrmsr reads from
+     * are put back afterwards. */
+    pub fn write_msr(&mut self, msr: u32, value: u64) -> Result<(), unicorn::Error> {
+        const WRMSR: [u8; 2] = [0x0f, 0x30];
+        let mut scratch = 0x10000;
+        while let Some(seg) = self
+            .mem
+            .iter()
+            .find(|s| s.aligned_start() < scratch + PAGE_SIZE as u64 && scratch < s.aligned_end())
+        {
+            scratch = seg.aligned_end();
+        }
+        let (rax, rcx, rdx, rip) = (
+            RegisterX86::RAX.to_i32(),
+            RegisterX86::RCX.to_i32(),
+            RegisterX86::RDX.to_i32(),
+            RegisterX86::RIP.to_i32(),
+        );
+        let saved = [rax, rcx, rdx, rip]
+            .iter()
+            .map(|&r| self.uc.reg_read(r).map(|v| (r, v)))
+            .collect::<Result<Vec<(i32, u64)>, unicorn::Error>>()?;
+        self.uc.mem_map(scratch, PAGE_SIZE, PROT_READ | PROT_EXEC)?;
+        self.uc.mem_write(scratch, &WRMSR)?;
+        self.uc.reg_write(rcx, u64::from(msr))?;
+        self.uc.reg_write(rax, value & 0xFFFF_FFFF)?;
+        self.uc.reg_write(rdx, value >> 32)?;
+        let res = self
+            .uc
+            .emu_start(scratch, scratch + WRMSR.len() as u64, 0, 1);
+        self.uc.mem_unmap(scratch, PAGE_SIZE)?;
+        for (r, v) in saved {
+            self.uc.reg_write(r, v)?;
+        }
+        res
+    }
+
+    /* Keeps note of the pages that the emulated code writes to, so that
+     * restore_state() only has to put those back. */
+    fn track_dirty_pages(&mut self) -> Result<(), unicorn::Error> {
+        let (begin, end) = match self.writeable_range() {
+            Some(range) => range,
+            None => return Ok(()),
         };
-        emu.save_state().unwrap();
-        emu
+        let dirty = self.dirty.clone();
+        let callback =
+            move |_uc: &Unicorn, _memtype: unicorn::MemType, addr: u64, size: usize, _val: i64| {
+                mark_dirty(&dirty, addr, size);
+                true
+            };
+        self.uc
+            .add_mem_hook(unicorn::MemHookType::MEM_WRITE, begin, end, callback)
+            .map(|_| ())
     }
 
     /// Saves the register context.
@@ -52,18 +150,63 @@ impl<'a> Engine<'a> {
     /// Saves both the register context and the state of writeable memory.
     pub fn save_state(&mut self) -> Result<(), unicorn::Error> {
         self.writeable_bak = Some(self.writeable_memory());
+        self.dirty.borrow_mut().clear();
         self.save_context()
     }
 
     /// Restores the register context and the state of writeable memory to
-    /// their state at the last save_state() event.
+    /// their state at the last save_state() event. Only the pages written
+    /// to since then are put back.
     pub fn restore_state(&mut self) -> Result<(), unicorn::Error> {
+        let dirty = self.dirty.borrow_mut().drain().collect::<Vec<u64>>();
+        for page in dirty {
+            if let Some(data) = self.saved_page(page) {
+                self.uc.mem_write(page, &data)?;
+            }
+        }
+        if *VERIFY_RESTORE {
+            assert!(
+                self.verify_restore(),
+                "[!] Writeable memory differs from the snapshot after restore_state"
+            );
+        };
+        self.restore_context()
+    }
+
+    /// Like restore_state(), but rewrites all of writeable memory, whether
+    /// it was touched or not.
+    pub fn restore_all(&mut self) -> Result<(), unicorn::Error> {
         for seg in self.writeable_bak.as_ref().unwrap() {
             self.uc.mem_write(seg.aligned_start(), &seg.data)?;
         }
+        self.dirty.borrow_mut().clear();
         self.restore_context()
     }
 
+    /// Checks that writeable memory is just as it was at the last
+    /// save_state(), by reading the whole of it back.
+    pub fn verify_restore(&self) -> bool {
+        match self.writeable_bak {
+            Some(ref bak) => self.writeable_memory() == *bak,
+            None => false,
+        }
+    }
+
+    /* The saved contents of a page: from the snapshot of writeable memory,
+     * or, for pages that were only ever written to from outside, like a
+     * preload into read-only memory, from the image itself. */
+    fn saved_page(&self, page: u64) -> Option<Vec<u8>> {
+        self.writeable_bak
+            .iter()
+            .flatten()
+            .chain(self.mem.iter())
+            .find(|s| s.aligned_start() <= page && page < s.aligned_start() + s.data.len() as u64)
+            .map(|s| {
+                let offset = (page - s.aligned_start()) as usize;
+                s.data[offset..usize::min(offset + PAGE_SIZE, s.data.len())].to_vec()
+            })
+    }
+
     /// Restores the register context.
     pub fn restore_context(&mut self) -> Result<(), unicorn::Error> {
         self.uc.context_restore(&self.saved_context)
@@ -72,7 +215,7 @@ impl<'a> Engine<'a> {
     /* method for the Engine trait */
     pub fn hard_reset(&mut self) {
         self.save_state().unwrap();
-        let (uc_arch, uc_mode) = self.arch.as_uc();
+        let (uc_arch, uc_mode) = self.arch.as_uc().unwrap();
         let uc = unicorn::Unicorn::new(uc_arch, uc_mode).unwrap();
         for seg in &self.mem {
             uc.mem_map(seg.aligned_start(), seg.aligned_size(), seg.perm)
@@ -80,10 +223,14 @@ impl<'a> Engine<'a> {
             uc.mem_write(seg.aligned_start(), &seg.data).unwrap();
         }
         self.uc = uc;
-        self.restore_state().unwrap() /* i want to see these crashes */
+        self.track_dirty_pages().unwrap();
+        self.restore_all().unwrap() /* i want to see these crashes */
     }
 
+    /// Writes to memory, marking the pages written as dirty, since the
+    /// write hook only sees writes made by the emulated code.
     pub fn mem_write(&mut self, addr: u64, data: &[u8]) -> Result<(), unicorn::Error> {
+        mark_dirty(&self.dirty, addr, data.len());
         self.uc.mem_write(addr, data)
     }
 
@@ -96,8 +243,16 @@ impl<'a> Engine<'a> {
     }
 
     pub fn mode(&self) -> Mode {
+        if let Arch::Mips(m) | Arch::Arm64(m) = self.arch {
+            /* endianness is fixed for the run, so there's nothing to ask */
+            return m;
+        };
         match self.uc_mode() {
-            unicorn::Mode::LITTLE_ENDIAN => Mode::Arm,
+            unicorn::Mode::LITTLE_ENDIAN => match self.arch {
+                Arch::Arm(_) => Mode::Arm,
+                _ => Mode::Le,
+            },
+            unicorn::Mode::BIG_ENDIAN => Mode::Be,
             unicorn::Mode::THUMB => Mode::Thumb,
             unicorn::Mode::MODE_64 => Mode::Bits64,
             unicorn::Mode::MODE_32 => Mode::Bits32,
@@ -114,21 +269,49 @@ impl<'a> Engine<'a> {
         }
     }
 
-    pub fn find_stack(&self) -> (u64, usize) {
-        let regions = self.uc.mem_regions().unwrap();
-        let mut bottom: Option<u64> = None;
-        let mut stack: Option<MemRegion> = None;
-        for region in regions.iter() {
-            if region.perms.intersects(PROT_READ | PROT_WRITE)
-                && region.begin >= bottom.unwrap_or(0)
-            {
-                bottom = Some(region.begin);
-                stack = Some(region.clone());
-            };
+    /// Maps an address in the slid image back to where it is in
+    /// MEM_IMAGE, so that what the chain did can be read against the
+    /// static image. Other addresses are left alone.
+    pub fn unslide(&self, addr: u64) -> u64 {
+        if self.slide == 0 {
+            return addr;
+        };
+        let slid = self
+            .mem
+            .iter()
+            .any(|s| s.slides() && s.aligned_start() <= addr && addr < s.aligned_end());
+        if slid {
+            addr - self.slide
+        } else {
+            addr
+        }
+    }
+
+    /// The inverse of `unslide`: where an address in MEM_IMAGE ended up
+    /// in this engine's slid image.
+    pub fn slide_addr(&self, addr: u64) -> u64 {
+        if self.slide == 0 {
+            return addr;
+        };
+        let slid = addr.wrapping_add(self.slide);
+        let moved = self
+            .mem
+            .iter()
+            .any(|s| s.slides() && s.aligned_start() <= slid && slid < s.aligned_end());
+        if moved {
+            slid
+        } else {
+            addr
         }
-        let stack = stack
-            .unwrap_or_else(|| panic!("[!] Could not find stack bottom! Regions: {:?}", regions));
-        (stack.begin, (stack.end - stack.begin) as usize)
+    }
+
+    pub fn find_stack(&self) -> (u64, usize) {
+        let stack = self
+            .mem
+            .iter()
+            .find(|s| s.segtype == SegType::Stack)
+            .expect("[!] No stack segment in the memory image");
+        (stack.aligned_start(), stack.aligned_size())
     }
 
     pub fn set_sp(&mut self, val: u64) -> Result<(), Error> {
@@ -137,6 +320,10 @@ impl<'a> Engine<'a> {
                 let sp = RegisterARM::SP as i32;
                 self.uc.reg_write(sp, val)
             }
+            Arch::Arm64(_) => {
+                let sp = RegisterARM64::SP as i32;
+                self.uc.reg_write(sp, val)
+            }
             Arch::Mips(_) => {
                 let sp = RegisterMIPS::SP as i32;
                 self.uc.reg_write(sp, val)
@@ -149,7 +336,7 @@ impl<'a> Engine<'a> {
                 let sp = RegisterX86::ESP as i32;
                 self.uc.reg_write(sp, val)
             }
-            _ => unreachable!("Not implemented"),
+            _ => Err(unicorn::Error::ARCH),
         }
     }
 
@@ -162,14 +349,17 @@ impl<'a> Engine<'a> {
             .iter()
             .filter(|r| r.perms.intersects(PROT_WRITE))
         {
-            let mut data: Vec<u8> = Vec::with_capacity((rgn.end - rgn.begin) as usize);
+            /* region ends are inclusive */
+            let size = (rgn.end - rgn.begin) as usize + 1;
+            let mut data: Vec<u8> = vec![0; size];
             self.uc.mem_read(rgn.begin, &mut data).unwrap();
             wmem.push(Seg {
                 addr: rgn.begin,
                 perm: rgn.perms,
-                memsz: (rgn.end - rgn.begin) as usize,
+                memsz: size,
                 data,
                 segtype: SegType::Load,
+                module: None,
             });
         }
         wmem
@@ -249,122 +439,340 @@ impl<'a> Engine<'a> {
         }
     }
 
-    pub fn hook_rets<F>(&mut self, callback: F) -> Result<unicorn::uc_hook, unicorn::Error>
+    /// Watches for control transfers, classifying each instruction once,
+    /// with a single exec hook, and calls back:
+    ///
+    /// - `on_ret` with the address and size of every return,
+    /// - `on_indirect_jump` likewise for every indirect jump or call,
+    /// - `on_transfer` with the source, destination and kind of every
+    ///   control transfer, when the instruction at its destination is about
+    ///   to execute.
+    ///
+    /// Returns and indirect jumps are reported once they take effect. On
+    /// architectures with delay slots, that's only after the instruction
+    /// in the delay slot has executed, which is when the first instruction
+    /// after it comes up. If emulation stops on the branch or its delay
+    /// slot, the return or jump never happened, but the transfer is left
+    /// pending, and can be collected with `take_pending_branch`.
+    pub fn hook_branches<R, J, T>(
+        &mut self,
+        on_ret: R,
+        on_indirect_jump: J,
+        on_transfer: T,
+    ) -> Result<unicorn::uc_hook, unicorn::Error>
     where
-        F: Fn(&Unicorn, u64, u32) -> () + 'static,
+        R: Fn(&Unicorn, u64, u32) -> () + 'static,
+        J: Fn(&Unicorn, u64, u32) -> () + 'static,
+        T: Fn(&Unicorn, u64, u64, BranchKind) -> () + 'static,
     {
-        //let (exec_start, exec_stop) = self.exec_mem_range();
-        let arch = ARCHITECTURE.with_mode(self.mode());
-        match arch {
-            Arch::X86(_) => {
-                /* KLUDGE -- not sure why instruction hooking won't work here. */
-                let _callback = move |uc: &Unicorn, addr, size| {
-                    let pc = addr;
-                    if size != 1 {
-                        return;
-                    };
-                    let mut bytecode: Vec<u8> = Vec::with_capacity(1);
-                    if uc.mem_read(pc, &mut bytecode).is_ok() {
-                        if !bytecode.is_empty() {
-                            if bytecode[0] == X86_RET {
-                                /* ret on x86 is C3 */
-                                callback(uc, addr, size)
-                            }
-                        }
-                    }
-                };
-                self.hook_exec_mem(_callback)
+        let delayed = has_delay_slot(self.arch);
+        let branch_kinds = self.branch_kinds.clone();
+        let pending = self.pending_branch.clone();
+        pending.set(None);
+        /* the size of the pending branch, and the address of the delay
+         * slot we're waiting to go by, if any */
+        let pending_size: Cell<u32> = Cell::new(0);
+        let delay_slot: Cell<Option<u64>> = Cell::new(None);
+        let report = move |uc: &Unicorn, addr: u64, size: u32, kind: BranchKind| {
+            if kind == BranchKind::Ret {
+                on_ret(uc, addr, size)
+            } else if kind.is_indirect() {
+                on_indirect_jump(uc, addr, size)
             }
-            Arch::Arm(Mode::Arm) => {
-                let _callback = move |uc: &Unicorn, addr, size| {
-                    let pc = addr; //read_pc(uc).unwrap();
-                    let mut bytecode: Vec<u8> = Vec::with_capacity(4);
-                    if uc.mem_read(pc, &mut bytecode).is_ok() {
-                        if !bytecode.is_empty() {
-                            if arm_ret(&bytecode) {
-                                callback(uc, addr, size)
-                            }
-                        }
-                    } else {
-                        panic!("Failed to read instruction")
-                    }
+        };
+        let _callback = move |uc: &Unicorn, addr: u64, size: u32| {
+            if let Some(slot) = delay_slot.take() {
+                if addr == slot {
+                    return;
+                }
+            };
+            if let Some((src, kind)) = pending.take() {
+                if delayed {
+                    report(uc, src, pending_size.get(), kind)
                 };
-                self.hook_exec_mem(_callback)
-            }
-            Arch::Arm(Mode::Thumb) => {
-                let _callback = move |uc: &Unicorn, addr, size| {
-                    let pc = addr; //read_pc(uc).unwrap();
-                    let mut bytecode: Vec<u8> = Vec::with_capacity(2);
-                    if uc.mem_read(pc, &mut bytecode).is_ok() {
-                        if !bytecode.is_empty() {
-                            if thumb_ret(&bytecode) {
-                                callback(uc, addr, size)
-                            }
-                        }
-                    } else {
-                        panic!("Failed to read instruction")
-                    }
+                on_transfer(uc, src, addr, kind)
+            };
+            if let Some(kind) = branch_kinds.classify(uc, addr, size) {
+                pending.set(Some((addr, kind)));
+                pending_size.set(size);
+                if delayed {
+                    delay_slot.set(Some(addr + u64::from(size)))
+                } else {
+                    report(uc, addr, size, kind)
                 };
-                self.hook_exec_mem(_callback)
-            }
-            _ => panic!("Unimplemented. Will need to tinker with unicorn-rs a bit."),
-        }
+            };
+        };
+        self.hook_exec_mem(_callback)
     }
 
-    pub fn hook_indirect_jumps<F>(
-        &mut self,
-        callback: F,
-    ) -> Result<unicorn::uc_hook, unicorn::Error>
+    /// Takes the control transfer, if any, that was still waiting for its
+    /// destination to execute when emulation stopped.
+    pub fn take_pending_branch(&self) -> Option<(u64, BranchKind)> {
+        self.pending_branch.take()
+    }
+
+    /// Hooks Linux system calls, however the architecture makes them:
+    /// `syscall` on x86_64, `sysenter` on i386 and `int 0x80` on both,
+    /// `svc` on ARM and AArch64, and `syscall` on MIPS. The callback is
+    /// passed the address of the instruction that made the call, and the
+    /// call's number and arguments.
+    pub fn hook_syscalls<F>(&mut self, callback: F) -> Result<Vec<unicorn::uc_hook>, Error>
     where
-        F: Fn(&Unicorn, u64, u32) -> () + 'static,
+        F: Fn(&Unicorn, u64, u64, Vec<u64>) -> () + Clone + 'static,
     {
-        let arch = ARCHITECTURE.with_mode(self.mode());
-        match arch {
-            Arch::X86(_) => {
-                let _callback = move |uc: &Unicorn, addr: u64, size: u32| {
-                    let size = u32::min(size, 15);
-                    let mut bytecode: Vec<u8> = Vec::with_capacity(size as usize);
-                    if uc.mem_read(addr, &mut bytecode).is_ok() {
-                        /* TODO Better indirect jump detector! */
-                        if !bytecode.is_empty() {
-                            if bytecode[0] == 0xFF {
-                                callback(uc, addr, size)
-                            }
-                        }
-                    } else {
-                        println!("Failed to read instruction! {:?}", bytecode)
-                    }
+        let arch = self.arch;
+        let intno = match arch {
+            Arch::X86(_) => X86_INT_SYSCALL,
+            Arch::Arm(_) | Arch::Arm64(_) => ARM_EXCP_SWI,
+            Arch::Mips(_) => MIPS_EXCP_SYSCALL,
+            _ => return Err(unicorn::Error::ARCH),
+        };
+        let mut hooks = Vec::new();
+        {
+            let callback = callback.clone();
+            let _callback = move |uc: &Unicorn, n: u32| {
+                if n != intno {
+                    return;
                 };
-                self.hook_exec_mem(_callback)
-            }
-            _ => panic!("hook_jumps not yet implemented for this architecture"),
+                /* by the time the hook runs, the pc has moved past the
+                 * instruction everywhere but on MIPS */
+                let pc = read_pc(uc).unwrap_or(0);
+                let pc = match arch {
+                    Arch::X86(_) => pc.wrapping_sub(2),
+                    Arch::Arm(_) if get_mode(uc) == Mode::Thumb => pc.wrapping_sub(2),
+                    Arch::Arm(_) | Arch::Arm64(_) => pc.wrapping_sub(4),
+                    _ => pc,
+                };
+                if let Ok((number, args)) = read_syscall(uc, arch, true) {
+                    callback(uc, pc, number, args)
+                }
+            };
+            hooks.push(self.uc.add_intr_hook(_callback)?);
         }
+        if let Arch::X86(mode) = arch {
+            let insn = match mode {
+                Mode::Bits64 => unicorn::InsnSysX86::SYSCALL,
+                _ => unicorn::InsnSysX86::SYSENTER,
+            };
+            /* unlike an interrupt, this hook runs with the pc still on
+             * the instruction */
+            let _callback = move |uc: &Unicorn| {
+                let pc = read_pc(uc).unwrap_or(0);
+                if let Ok((number, args)) = read_syscall(uc, arch, false) {
+                    callback(uc, pc, number, args)
+                }
+            };
+            hooks.push(self.uc.add_insn_sys_hook(insn, 1, 0, _callback)?);
+        };
+        Ok(hooks)
     }
 
-    pub fn hook_writeable_mem<F>(&mut self, callback: F) -> Result<unicorn::uc_hook, Error>
+    /// Hooks reads, writes and fetches that hit unmapped memory or break
+    /// its permissions, wherever they happen. The callback should return
+    /// false, to let the access fail.
+    pub fn hook_mem_faults<F>(&mut self, callback: F) -> Result<unicorn::uc_hook, Error>
     where
         F: Fn(&Unicorn, unicorn::MemType, u64, usize, i64) -> bool + 'static,
     {
-        let writeable = self.writeable_memory();
+        self.uc.add_mem_hook(
+            unicorn::MemHookType::MEM_READ_UNMAPPED
+                | unicorn::MemHookType::MEM_WRITE_UNMAPPED
+                | unicorn::MemHookType::MEM_FETCH_UNMAPPED
+                | unicorn::MemHookType::MEM_READ_PROT
+                | unicorn::MemHookType::MEM_WRITE_PROT
+                | unicorn::MemHookType::MEM_FETCH_PROT,
+            0,
+            u64::max_value(),
+            callback,
+        )
+    }
+
+    /// Hooks reads from each of the given ranges of addresses, or from
+    /// anywhere, if there are none.
+    pub fn hook_mem_reads<F>(
+        &mut self,
+        ranges: &[(u64, u64)],
+        callback: F,
+    ) -> Result<Vec<unicorn::uc_hook>, Error>
+    where
+        F: Fn(&Unicorn, unicorn::MemType, u64, usize, i64) -> bool + Clone + 'static,
+    {
+        if ranges.is_empty() {
+            return self
+                .uc
+                .add_mem_hook(
+                    unicorn::MemHookType::MEM_READ,
+                    0,
+                    u64::max_value(),
+                    callback,
+                )
+                .map(|h| vec![h]);
+        };
+        let mut hooks = Vec::new();
+        for (begin, end) in ranges {
+            hooks.push(self.uc.add_mem_hook(
+                unicorn::MemHookType::MEM_READ,
+                *begin,
+                *end,
+                callback.clone(),
+            )?);
+        }
+        Ok(hooks)
+    }
+
+    /* The span from the lowest writeable address to the highest. Only the
+     * region table is consulted; reading the memory itself is costly. */
+    fn writeable_range(&self) -> Option<(u64, u64)> {
         let mut begin = None;
         let mut end = None;
-        for seg in &writeable {
-            let b = seg.aligned_start();
-            let e = seg.aligned_end();
-            if begin == None || b < begin.unwrap() {
-                begin = Some(b)
+        for rgn in self
+            .uc
+            .mem_regions()
+            .unwrap()
+            .iter()
+            .filter(|r| r.perms.intersects(PROT_WRITE))
+        {
+            if begin == None || rgn.begin < begin.unwrap() {
+                begin = Some(rgn.begin)
             };
-            if end == None || e > end.unwrap() {
-                end = Some(e)
+            if end == None || rgn.end > end.unwrap() {
+                end = Some(rgn.end)
             };
         }
-        assert!(begin != None && end != None);
-        self.uc.add_mem_hook(
-            unicorn::MemHookType::MEM_WRITE,
-            begin.unwrap(),
-            end.unwrap(),
-            callback,
-        )
+        match (begin, end) {
+            (Some(b), Some(e)) => Some((b, e)),
+            _ => None,
+        }
+    }
+
+    pub fn hook_writeable_mem<F>(&mut self, callback: F) -> Result<unicorn::uc_hook, Error>
+    where
+        F: Fn(&Unicorn, unicorn::MemType, u64, usize, i64) -> bool + 'static,
+    {
+        let (begin, end) = self
+            .writeable_range()
+            .expect("[!] No writeable memory to hook");
+        self.uc
+            .add_mem_hook(unicorn::MemHookType::MEM_WRITE, begin, end, callback)
+    }
+}
+
+/// The kinds of control transfer we distinguish.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BranchKind {
+    Ret,
+    Jump,
+    IndirectJump,
+    Call,
+    IndirectCall,
+}
+
+impl BranchKind {
+    /// True if the target comes from a register or memory, rather than
+    /// the instruction itself. Returns are counted separately.
+    pub fn is_indirect(self) -> bool {
+        match self {
+            BranchKind::IndirectJump | BranchKind::IndirectCall => true,
+            _ => false,
+        }
+    }
+}
+
+pub fn has_delay_slot(arch: Arch) -> bool {
+    match arch {
+        Arch::Mips(_) => true,
+        _ => false,
+    }
+}
+
+/// Classifies a single instruction as a kind of branch, or not. The
+/// instruction groups decoded by capstone are the main source, but we
+/// fall back on bit patterns for the returns that capstone doesn't mark
+/// as such (pop {pc} on ARM, jr $ra on MIPS), and for RISC-V, which
+/// capstone can't decode at all.
+pub fn branch_kind(bytes: &[u8], mode: Mode) -> Option<BranchKind> {
+    let arch = ARCHITECTURE.with_mode(mode);
+    let size = bytes.len();
+    let (ret, indirect) = match arch {
+        Arch::X86(_) if size >= 1 => (x86_ret(bytes), false),
+        Arch::Arm(Mode::Thumb) if size >= 2 => (thumb_ret(bytes), false),
+        Arch::Arm(_) if size >= 4 => (arm_ret(bytes), false),
+        Arch::Arm64(_) if size >= 4 => {
+            let w = arm64_word(bytes);
+            (arm64_ret(w), arm64_indirect_jump(w))
+        }
+        Arch::Mips(m) if size >= 4 => {
+            let w = mips_word(bytes, m);
+            (mips_ret(w), mips_indirect_jump(w))
+        }
+        Arch::RiscV(_) if size >= 2 => {
+            let size = usize::min(size, 4);
+            let w = insn_word(&bytes[..size], Mode::Le);
+            (
+                riscv_ret(w, size as u32),
+                riscv_indirect_jump(w, size as u32),
+            )
+        }
+        Arch::Ppc(m) | Arch::Ppc64(m) if size >= 4 => {
+            let w = insn_word(&bytes[..4], m);
+            (ppc_ret(w, 4), ppc_indirect_jump(w, 4))
+        }
+        _ => (false, false),
+    };
+    if ret {
+        return Some(BranchKind::Ret);
+    };
+    match disas::branch_kind(bytes, mode) {
+        None if indirect => Some(BranchKind::IndirectJump),
+        kind => kind,
+    }
+}
+
+/// Reads the instruction at addr out of a running emulator, and
+/// classifies it as a kind of branch, or not.
+pub fn read_branch_kind(uc: &Unicorn, addr: u64, size: u32, mode: Mode) -> Option<BranchKind> {
+    let mut bytecode: Vec<u8> = vec![0; u32::min(u32::max(size, 1), 15) as usize];
+    if uc.mem_read(addr, &mut bytecode).is_err() {
+        return None;
+    };
+    branch_kind(&bytecode, mode)
+}
+
+/* Classifying an instruction means disassembling it, so each engine
+ * remembers what it found, at least for code that can't be rewritten.
+ * The addresses are the engine's own, slid or not. */
+struct BranchKindCache {
+    kinds: RefCell<HashMap<(u64, Mode), Option<BranchKind>>>,
+    read_only: Vec<(u64, u64)>,
+}
+
+impl BranchKindCache {
+    fn new(mem: &[Seg]) -> Self {
+        BranchKindCache {
+            kinds: RefCell::new(HashMap::new()),
+            read_only: mem
+                .iter()
+                .filter(|s| !s.is_writeable())
+                .map(|s| (s.aligned_start(), s.aligned_end()))
+                .collect(),
+        }
+    }
+
+    fn classify(&self, uc: &Unicorn, addr: u64, size: u32) -> Option<BranchKind> {
+        let mode = get_mode(uc);
+        let cacheable = self
+            .read_only
+            .iter()
+            .any(|&(start, end)| start <= addr && addr < end);
+        if !cacheable {
+            return read_branch_kind(uc, addr, size, mode);
+        };
+        if let Some(kind) = self.kinds.borrow().get(&(addr, mode)) {
+            return *kind;
+        };
+        let kind = read_branch_kind(uc, addr, size, mode);
+        self.kinds.borrow_mut().insert((addr, mode), kind);
+        kind
     }
 }
 
@@ -373,11 +781,13 @@ impl<'a> Engine<'a> {
 pub fn whats_pc() -> i32 {
     match *ARCHITECTURE {
         Arch::Arm(_) => RegisterARM::PC.to_i32(),
+        Arch::Arm64(_) => RegisterARM64::PC.to_i32(),
         Arch::Mips(_) => RegisterMIPS::PC.to_i32(),
         Arch::X86(Mode::Bits64) => RegisterX86::RIP.to_i32(),
         Arch::X86(Mode::Bits32) => RegisterX86::EIP.to_i32(),
         Arch::X86(Mode::Bits16) => RegisterX86::IP.to_i32(),
-        _ => panic!("unimplemented"),
+        /* there's no Engine for any other, so no pc to read */
+        _ => unreachable!("{:?} can't be emulated", *ARCHITECTURE),
     }
 }
 
@@ -392,21 +802,98 @@ pub fn read_pc(uc: &Unicorn) -> Result<u64, unicorn::Error> {
 pub fn whats_accum() -> i32 {
     match *ARCHITECTURE {
         Arch::Arm(_) => RegisterARM::R0.to_i32(),
+        Arch::Arm64(_) => RegisterARM64::X0.to_i32(),
+        Arch::Mips(_) => RegisterMIPS::V0.to_i32(),
         Arch::X86(Mode::Bits64) => RegisterX86::RAX.to_i32(),
         Arch::X86(Mode::Bits32) => RegisterX86::EAX.to_i32(),
         Arch::X86(Mode::Bits16) => RegisterX86::AX.to_i32(),
-        _ => panic!("not yet implemented"),
+        _ => unreachable!("{:?} can't be emulated", *ARCHITECTURE),
     }
 }
 
+/// Reads the number and arguments of a Linux system call from where
+/// the architecture's calling convention puts them. An `int 0x80` on
+/// x86_64 goes through the i386 convention, so `int80` says which was
+/// used there; elsewhere it makes no difference.
+pub fn read_syscall(
+    uc: &Unicorn,
+    arch: Arch,
+    int80: bool,
+) -> Result<(u64, Vec<u64>), unicorn::Error> {
+    let (number, args) = match arch {
+        Arch::X86(Mode::Bits64) if !int80 => (
+            RegisterX86::RAX.to_i32(),
+            regids(&[
+                RegisterX86::RDI,
+                RegisterX86::RSI,
+                RegisterX86::RDX,
+                RegisterX86::R10,
+                RegisterX86::R8,
+                RegisterX86::R9,
+            ]),
+        ),
+        Arch::X86(_) => (
+            RegisterX86::EAX.to_i32(),
+            regids(&[
+                RegisterX86::EBX,
+                RegisterX86::ECX,
+                RegisterX86::EDX,
+                RegisterX86::ESI,
+                RegisterX86::EDI,
+                RegisterX86::EBP,
+            ]),
+        ),
+        Arch::Arm(_) => (
+            RegisterARM::R7.to_i32(),
+            regids(&[
+                RegisterARM::R0,
+                RegisterARM::R1,
+                RegisterARM::R2,
+                RegisterARM::R3,
+                RegisterARM::R4,
+                RegisterARM::R5,
+            ]),
+        ),
+        Arch::Arm64(_) => (
+            RegisterARM64::X8.to_i32(),
+            regids(&[
+                RegisterARM64::X0,
+                RegisterARM64::X1,
+                RegisterARM64::X2,
+                RegisterARM64::X3,
+                RegisterARM64::X4,
+                RegisterARM64::X5,
+            ]),
+        ),
+        /* o32 passes a fifth and sixth argument on the stack */
+        Arch::Mips(_) => (
+            RegisterMIPS::V0.to_i32(),
+            regids(&[
+                RegisterMIPS::A0,
+                RegisterMIPS::A1,
+                RegisterMIPS::A2,
+                RegisterMIPS::A3,
+            ]),
+        ),
+        _ => return Err(unicorn::Error::ARCH),
+    };
+    let number = uc.reg_read(number)?;
+    let args = args
+        .into_iter()
+        .map(|r| uc.reg_read(r))
+        .collect::<Result<Vec<u64>, unicorn::Error>>()?;
+    Ok((number, args))
+}
+
 pub fn uc_general_registers(uc: &Unicorn) -> Result<Vec<u64>, unicorn::Error> {
     /* FIXME: optimize away this match, refer to a static instead */
     let regids = match *ARCHITECTURE {
         Arch::Arm(_) => regids(&ARM_REGISTERS),
+        Arch::Arm64(_) => regids(&ARM64_REGISTERS),
         Arch::Mips(_) => regids(&MIPS_REGISTERS),
         Arch::X86(Mode::Bits64) => regids(&X86_64_REGISTERS),
         Arch::X86(Mode::Bits32) => regids(&X86_32_REGISTERS),
-        _ => unreachable!("Not implemented"),
+        _ => return Err(unicorn::Error::ARCH),
     };
     Ok(regids
         .iter()
@@ -423,6 +910,96 @@ where
     regs.iter().map(|x| x.to_i32()).collect::<Vec<i32>>()
 }
 
+fn find_register<T>(regs: &[T], name: &str) -> Option<i32>
+where
+    T: Register + fmt::Debug,
+{
+    regs.iter()
+        .find(|r| format!("{:?}", r).eq_ignore_ascii_case(name))
+        .map(|r| r.to_i32())
+}
+
+/// The names of an architecture's general registers, as register_id
+/// knows them. RISC-V and PowerPC have their tables, in ABI order, for
+/// naming registers, though without an emulator there are no ids.
+pub fn register_names(arch: Arch) -> Vec<String> {
+    fn debug_names<T: fmt::Debug>(regs: &[T]) -> Vec<String> {
+        regs.iter()
+            .map(|r| format!("{:?}", r).to_lowercase())
+            .collect()
+    }
+    match arch {
+        Arch::Arm(_) => debug_names(&ARM_REGISTERS),
+        Arch::Arm64(_) => debug_names(&ARM64_REGISTERS),
+        Arch::Mips(_) => debug_names(&MIPS_REGISTERS),
+        Arch::X86(Mode::Bits64) => debug_names(&X86_64_REGISTERS),
+        Arch::X86(_) => debug_names(&X86_32_REGISTERS),
+        Arch::RiscV(_) => RISCV_REGISTERS.iter().map(|r| r.to_string()).collect(),
+        Arch::Ppc(_) | Arch::Ppc64(_) => PPC_REGISTERS.iter().map(|r| r.to_string()).collect(),
+    }
+}
+
+/// Looks up a register by the name unicorn gives it, in any case.
+/// Architectures that can't be emulated have no ids to give.
+pub fn register_id(arch: Arch, name: &str) -> Option<i32> {
+    match arch {
+        Arch::Arm(_) => find_register(&ARM_REGISTERS, name)
+            .or_else(|| find_register(&[RegisterARM::CPSR], name)),
+        Arch::Arm64(_) => find_register(&ARM64_REGISTERS, name),
+        Arch::Mips(_) => find_register(&MIPS_REGISTERS, name),
+        Arch::X86(Mode::Bits64) => find_register(&X86_64_REGISTERS, name).or_else(|| {
+            find_register(
+                &[
+                    RegisterX86::R8,
+                    RegisterX86::CS,
+                    RegisterX86::DS,
+                    RegisterX86::ES,
+                    RegisterX86::FS,
+                    RegisterX86::GS,
+                    RegisterX86::SS,
+                ],
+                name,
+            )
+        }),
+        Arch::X86(_) => find_register(&X86_32_REGISTERS, name),
+        _ => None,
+    }
+}
+
+/// The model specific registers that a snapshot can set by name:
+/// the fs and gs bases, which the core file's prstatus carries.
+pub fn msr_id(arch: Arch, name: &str) -> Option<u32> {
+    match (arch, name) {
+        (Arch::X86(Mode::Bits64), "fs_base") => Some(0xC000_0100),
+        (Arch::X86(Mode::Bits64), "gs_base") => Some(0xC000_0101),
+        _ => None,
+    }
+}
+
+lazy_static! {
+    static ref WARNED_REGISTERS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// Complains about a register that can't be set, once per name, rather
+/// than once for every engine or every hatch that tries.
+pub fn warn_unknown_register(name: &str, arch: Arch) {
+    if WARNED_REGISTERS.lock().unwrap().insert(name.to_string()) {
+        match arch {
+            Arch::RiscV(_) | Arch::Ppc(_) | Arch::Ppc64(_) => crate::notice!(
+                "[!] Can't set {} on {:?}, which can only be disassembled",
+                name,
+                arch
+            ),
+            _ => crate::notice!(
+                "[!] No register named {} on {:?}; try one of {}",
+                name,
+                arch,
+                register_names(arch).join(" ")
+            ),
+        }
+    }
+}
+
 pub fn mem_image_deep_copy<'a>() -> MemImage<'a> {
     let mut mi = Vec::new();
     for seg in MEM_IMAGE.to_vec() {
@@ -447,7 +1024,7 @@ fn uc_mem_table(emu: &Unicorn) -> Vec<(u64, usize, unicorn::Protection, Vec<u8>)
         let begin = region.begin;
         let size = (region.end - region.begin) as usize + 1;
         let perms = region.perms;
-        let mut data: Vec<u8> = Vec::with_capacity(size);
+        let mut data: Vec<u8> = vec![0; size];
         if emu.mem_read(begin, &mut data).is_ok() {
             let ptr = data;
             table.push((begin, size, perms, ptr))
@@ -456,14 +1033,19 @@ fn uc_mem_table(emu: &Unicorn) -> Vec<(u64, usize, unicorn::Protection, Vec<u8>)
     table
 } /* from raw Unicorn instance. Useful inside callbacks, for disassembling */
 pub fn get_mode(uc: &Unicorn) -> Mode {
-    /* TODO keep a global static architecture variable, for reference
-     * in situations like these. for now, we're just assuming ARM, but
-     * plan to extend the system to cover, at least, MIPS, too.
-     */
+    /* A mode of 0 just means little endian, which only tells us
+     * something on ARM, where it means we're not in Thumb mode. */
+    if let Arch::Mips(m) | Arch::Arm64(m) = *ARCHITECTURE {
+        return m;
+    };
     let raw = uc.query(unicorn::Query::MODE);
 
     match raw {
-        Ok(0b00000) => Mode::Arm,
+        Ok(0b00000) => match *ARCHITECTURE {
+            Arch::Arm(_) => Mode::Arm,
+            _ => Mode::Le,
+        },
+        Ok(0x40000000) => Mode::Be,
         Ok(0b10000) => Mode::Thumb,
         Ok(0b01000) => Mode::Bits64,
         Ok(0b00100) => Mode::Bits32,
@@ -473,15 +1055,68 @@ pub fn get_mode(uc: &Unicorn) -> Mode {
     }
 }
 
+const PAGE_SIZE: usize = 0x1000;
+
+/* Notes every page touched by a write of size bytes at addr. */
+fn mark_dirty(dirty: &RefCell<HashSet<u64>>, addr: u64, size: usize) {
+    if size == 0 {
+        return;
+    };
+    let page_mask = !(PAGE_SIZE as u64 - 1);
+    let last = (addr + size as u64 - 1) & page_mask;
+    let mut page = addr & page_mask;
+    let mut dirty = dirty.borrow_mut();
+    while page <= last {
+        dirty.insert(page);
+        page += PAGE_SIZE as u64;
+    }
+}
+
+/* The first of the loaded files' segments that would run into memory that
+ * stays put, like the stack or the low page, if they were moved up by
+ * slide. */
+fn slide_collision(mem: &[Seg], slide: u64) -> Option<&Seg> {
+    mem.iter().filter(|s| s.slides()).find(|s| {
+        let (start, end) = (s.aligned_start() + slide, s.aligned_end() + slide);
+        mem.iter()
+            .filter(|t| !t.slides())
+            .any(|t| t.aligned_start() < end && start < t.aligned_end())
+    })
+}
+
+/// Whether the loaded files can be moved up by slide without running into
+/// the stack or anything else that stays where it is.
+pub fn slide_fits(slide: u64) -> bool {
+    slide_collision(&MEM_IMAGE, slide).is_none()
+}
+
 pub fn init_emulator<'a>(
     archmode: Arch,
     unsafely: bool,
+    slide: u64,
 ) -> Result<(Box<Unicorn<'a>>, MemImage<'a>), unicorn::Error> {
-    let (arch, mode) = archmode.as_uc();
+    let (arch, mode) = archmode.as_uc()?;
 
     let uc = Unicorn::new(arch, mode)?;
 
     let mut mem: MemImage = mem_image_deep_copy();
+    if slide != 0 {
+        if let Some(seg) = slide_collision(&mem, slide) {
+            crate::notice!(
+                "[!] Slid by 0x{:x}, {} would overlap the stack or low memory",
+                slide,
+                seg
+            );
+            return Err(unicorn::Error::MAP);
+        };
+        for seg in mem.iter_mut().filter(|s| s.slides()) {
+            seg.addr += slide;
+        }
+        /* the GOT and anything else relocated has to follow */
+        if SNAPSHOT.is_none() {
+            relocate(&mut mem, slide);
+        };
+    };
 
     for seg in &mut mem {
         if unsafely {
@@ -502,9 +1137,14 @@ pub fn init_emulator<'a>(
 }
 
 pub fn align_inst_addr(addr: u64, mode: Mode) -> u64 {
+    if let Arch::RiscV(_) = *ARCHITECTURE {
+        /* allowing for the compressed instruction extension */
+        return addr & !1;
+    };
     match mode {
-        Mode::Arm | Mode::Le | Mode::Be => addr & 0xFFFFFFFC,
-        Mode::Thumb => (addr & 0xFFFFFFFE) | 1,
+        Mode::Arm | Mode::Le | Mode::Be => addr & !3,
+        /* the interworking bit is set when the address is packed */
+        Mode::Thumb => addr & !1,
         Mode::Bits16 => addr & 0xFFFF,
         Mode::Bits32 => addr & 0xFFFFFFFF,
         Mode::Bits64 => addr & 0xFFFFFFFFFFFFFFFF,
@@ -518,7 +1158,8 @@ pub fn calc_sp_delta(addr: u64, mode: Mode) -> usize {
         Arch::X86(Mode::Bits64) => x86_64_calc_sp_delta(addr),
         Arch::Arm(Mode::Arm) => arm_calc_sp_delta(addr),
         Arch::Arm(Mode::Thumb) => thumb_calc_sp_delta(addr),
-        _ => panic!("unimplemented sp_delta arch/mode"),
+        Arch::Arm64(_) => arm64_calc_sp_delta(addr),
+        _ => 0, /* no analysis for this arch/mode yet */
     }
 }
 
@@ -536,7 +1177,70 @@ fn thumb_calc_sp_delta(_addr: u64) -> usize {
     0
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/* How far we'll look ahead for the end of a gadget. */
+const MAX_GADGET_INSTS: u64 = 16;
+
+/* Sums the stack pointer adjustments made by the instructions from
+ * addr up to and including the next return or indirect branch, and
+ * gives the total in words. */
+fn arm64_calc_sp_delta(addr: u64) -> usize {
+    let mut delta: i64 = 0;
+    for i in 0..MAX_GADGET_INSTS {
+        let w = match read_static_mem(addr + i * 4, 4) {
+            Some(ref bytes) if bytes.len() == 4 => arm64_word(bytes),
+            _ => break,
+        };
+        delta += arm64_inst_sp_delta(w);
+        if arm64_ret(w) || arm64_indirect_jump(w) {
+            break;
+        };
+    }
+    if delta > 0 {
+        delta as usize / 8
+    } else {
+        0
+    }
+}
+
+fn sign_extend(x: u32, bits: u32) -> i64 {
+    ((u64::from(x) << (64 - bits)) as i64) >> (64 - bits)
+}
+
+/* The change a single instruction makes to sp, in bytes. We only look
+ * for the forms that compilers use to adjust the stack: add/sub sp,
+ * sp, #imm, and ldp/stp/ldr/str with sp as a writeback base. */
+fn arm64_inst_sp_delta(w: u32) -> i64 {
+    let rn = (w >> 5) & 0x1F;
+    let rd = w & 0x1F;
+    if rn != 31 {
+        return 0;
+    };
+    let imm12 = || {
+        let imm = i64::from((w >> 10) & 0xFFF);
+        if (w >> 22) & 1 == 1 {
+            imm << 12
+        } else {
+            imm
+        }
+    };
+    if w & 0xFF800000 == 0x91000000 && rd == 31 {
+        /* add sp, sp, #imm */
+        imm12()
+    } else if w & 0xFF800000 == 0xD1000000 && rd == 31 {
+        /* sub sp, sp, #imm */
+        -imm12()
+    } else if w & 0xFE800000 == 0xA8800000 {
+        /* ldp/stp x, x, [sp], #imm and ldp/stp x, x, [sp, #imm]! */
+        sign_extend((w >> 15) & 0x7F, 7) * 8
+    } else if w & 0xFFA00400 == 0xF8000400 {
+        /* ldr/str x, [sp], #imm and ldr/str x, [sp, #imm]! */
+        sign_extend((w >> 12) & 0x1FF, 9)
+    } else {
+        0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Mode {
     Arm,
     Thumb,
@@ -564,31 +1268,56 @@ impl Mode {
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Arch {
     Arm(Mode),
+    Arm64(Mode),
     Mips(Mode),
     X86(Mode),
+    /* The bundled Unicorn has no backend for these, so binaries for
+     * them can only be loaded and disassembled, never emulated: there's
+     * no Engine for them, and evolution refuses to start. */
+    RiscV(Mode),
+    Ppc(Mode),
+    Ppc64(Mode),
 }
 
 impl Arch {
-    pub fn as_uc(self) -> (unicorn::Arch, unicorn::Mode) {
+    /// Unicorn's names for the architecture and mode, or ARCH for those
+    /// it can't emulate.
+    pub fn as_uc(self) -> Result<(unicorn::Arch, unicorn::Mode), unicorn::Error> {
         match self {
-            Arch::Arm(ref m) => (unicorn::Arch::ARM, m.as_uc()),
-            Arch::Mips(ref m) => (unicorn::Arch::MIPS, m.as_uc()),
-            Arch::X86(ref m) => (unicorn::Arch::X86, m.as_uc()),
+            Arch::Arm(ref m) => Ok((unicorn::Arch::ARM, m.as_uc())),
+            Arch::Arm64(ref m) => Ok((unicorn::Arch::ARM64, m.as_uc())),
+            /* unicorn wants the word size as well as the endianness */
+            Arch::Mips(ref m) => Ok((unicorn::Arch::MIPS, m.as_uc() | unicorn::Mode::MODE_32)),
+            Arch::X86(ref m) => Ok((unicorn::Arch::X86, m.as_uc())),
+            Arch::RiscV(_) | Arch::Ppc(_) | Arch::Ppc64(_) => Err(unicorn::Error::ARCH),
         }
     }
+
+    /// Whether the bundled Unicorn can run code for the architecture.
+    pub fn emulable(self) -> bool {
+        self.as_uc().is_ok()
+    }
     pub fn mode(self) -> Mode {
         match self {
             Arch::Arm(ref m) => *m,
+            Arch::Arm64(ref m) => *m,
             Arch::Mips(ref m) => *m,
             Arch::X86(ref m) => *m,
+            Arch::RiscV(ref m) => *m,
+            Arch::Ppc(ref m) => *m,
+            Arch::Ppc64(ref m) => *m,
         }
     }
     /// Returns a new Arch enum with specified mode
     pub fn with_mode(self, mode: Mode) -> Arch {
         match self {
             Arch::Arm(_) => Arch::Arm(mode),
+            Arch::Arm64(_) => Arch::Arm64(mode),
             Arch::Mips(_) => Arch::Mips(mode),
             Arch::X86(_) => Arch::X86(mode),
+            Arch::RiscV(_) => Arch::RiscV(mode),
+            Arch::Ppc(_) => Arch::Ppc(mode),
+            Arch::Ppc64(_) => Arch::Ppc64(mode),
         }
     }
     //pub fn as_cs(&self) -> capstone::
@@ -621,6 +1350,10 @@ pub enum SegType {
     GnuEhFrame,
     GnuStack,
     GnuRelRo,
+    /* where the payload goes */
+    Stack,
+    /* stand-ins for functions that no loaded module defines */
+    Stubs,
     Other, /* KLUDGE: a temporary catchall */
 }
 
@@ -658,9 +1391,19 @@ pub struct Seg {
     pub perm: Perm,
     pub segtype: SegType,
     pub data: Vec<u8>,
+    /* index into MODULES, or None for the stack and other memory that
+     * doesn't come from a file */
+    pub module: Option<usize>,
 }
 
 impl Seg {
+    /// Whether the segment moves with the image under ASLR: those of the
+    /// loaded files, and the stubs standing in for their imports, but not
+    /// the stack or the low page.
+    pub fn slides(&self) -> bool {
+        self.module.is_some() || self.segtype == SegType::Stubs
+    }
+
     pub fn deep_copy(&self) -> Seg {
         Seg {
             addr: self.addr,
@@ -668,6 +1411,7 @@ impl Seg {
             perm: self.perm,
             segtype: self.segtype,
             data: self.data.clone(),
+            module: self.module,
         }
     }
 
@@ -688,13 +1432,84 @@ impl Seg {
             perm: uc_perm,
             segtype: SegType::new(phdr.p_type),
             data: Vec::new(),
+            module: Some(0),
         };
-        println!("[from_phdr()] s = {}", s);
+        crate::notice!("[from_phdr()] s = {}", s);
         let size = (s.aligned_end() - s.aligned_start()) as usize;
         s.data = vec![UNINITIALIZED_BYTE; size];
         s
     }
 
+    /// Maps a PE section at its virtual address, relative to the image
+    /// base, with its raw data copied in. Any space beyond the raw data,
+    /// such as uninitialized data, is zero-filled.
+    pub fn from_pe_section(section: &pe::section_table::SectionTable, image_base: u64) -> Self {
+        let mut uc_perm = unicorn::Protection::NONE;
+        if section.characteristics & IMAGE_SCN_MEM_EXECUTE != 0 {
+            uc_perm |= PROT_EXEC
+        };
+        if section.characteristics & IMAGE_SCN_MEM_WRITE != 0 {
+            uc_perm |= PROT_WRITE
+        };
+        if section.characteristics & IMAGE_SCN_MEM_READ != 0 {
+            uc_perm |= PROT_READ
+        };
+        /* the virtual size is allowed to be zero, in which case the
+         * raw size is what counts */
+        let memsz = match section.virtual_size {
+            0 => section.size_of_raw_data,
+            n => n,
+        } as usize;
+        let mut s = Seg {
+            addr: image_base + u64::from(section.virtual_address),
+            memsz,
+            perm: uc_perm,
+            segtype: SegType::Load,
+            data: Vec::new(),
+            module: Some(0),
+        };
+        crate::notice!("[from_pe_section()] {:?} s = {}", section.name(), s);
+        s.data = vec![UNINITIALIZED_BYTE; s.aligned_size()];
+        let i = section.pointer_to_raw_data as usize;
+        let raw_size = usize::min(section.size_of_raw_data as usize, memsz);
+        let j = usize::min(i + raw_size, CODE_BUFFER.len());
+        if i < j {
+            let v_off = (s.addr - s.aligned_start()) as usize;
+            s.data[v_off..v_off + (j - i)].copy_from_slice(&CODE_BUFFER[i..j]);
+        };
+        s
+    }
+
+    /// Maps a Mach-O segment with its initial protections. The file data
+    /// comes from the segment itself, since in a fat binary its offset is
+    /// relative to the slice, not to the file.
+    pub fn from_macho_segment(segment: &mach::segment::Segment) -> Self {
+        let mut uc_perm = unicorn::Protection::NONE;
+        if segment.initprot & VM_PROT_EXECUTE != 0 {
+            uc_perm |= PROT_EXEC
+        };
+        if segment.initprot & VM_PROT_WRITE != 0 {
+            uc_perm |= PROT_WRITE
+        };
+        if segment.initprot & VM_PROT_READ != 0 {
+            uc_perm |= PROT_READ
+        };
+        let mut s = Seg {
+            addr: segment.vmaddr,
+            memsz: segment.vmsize as usize,
+            perm: uc_perm,
+            segtype: SegType::Load,
+            data: Vec::new(),
+            module: Some(0),
+        };
+        crate::notice!("[from_macho_segment()] {:?} s = {}", segment.name(), s);
+        s.data = vec![UNINITIALIZED_BYTE; s.aligned_size()];
+        let v_off = (s.addr - s.aligned_start()) as usize;
+        let len = usize::min(segment.data.len(), s.memsz);
+        s.data[v_off..v_off + len].copy_from_slice(&segment.data[..len]);
+        s
+    }
+
     pub fn is_executable(&self) -> bool {
         self.perm.intersects(PROT_EXEC)
     }
@@ -708,28 +1523,106 @@ impl Seg {
     }
 
     pub fn aligned_start(&self) -> u64 {
-        self.addr & 0xFFFFF000
+        self.addr & !0xFFF
     }
+    /* Round up to the page, but no further: PE sections are often packed
+     * back to back, and an extra page would overlap the next one. */
     pub fn aligned_end(&self) -> u64 {
-        (self.addr + (self.memsz as u64) + 0x1000) & 0xFFFFF000
+        (self.addr + (self.memsz as u64) + 0xFFF) & !0xFFF
     }
     pub fn aligned_size(&self) -> usize {
-        ((self.addr as usize & 0x0FFF) + self.memsz as usize + 0x1000) & 0xFFFFF000
+        ((self.addr as usize & 0x0FFF) + self.memsz as usize + 0xFFF) & !0xFFF
     }
     pub fn loadable(&self) -> bool {
         self.segtype.loadable()
     }
 }
 
-impl Display for Seg {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "[aligned {:08x} -- {:08x}: {:?}]",
-            self.aligned_start(),
-            self.aligned_end(),
-            self.perm
-        )
+/* With a SectionAlignment under the page size, as in many small or
+ * driver images, the headers and sections can share pages, which
+ * unicorn can't map twice. Each shared page is cut out into a segment
+ * of its own, with the permissions of everything on it, and the rest of
+ * each segment keeps its own, so that only those pages, and not the
+ * whole run of them, end up writeable and executable at once. */
+fn merge_shared_pages(mut segs: Vec<Seg>) -> Vec<Seg> {
+    let page = PAGE_SIZE as u64;
+    let mut touches: HashMap<u64, usize> = HashMap::new();
+    for seg in &segs {
+        for p in (seg.aligned_start()..seg.aligned_end()).step_by(PAGE_SIZE) {
+            *touches.entry(p).or_insert(0) += 1;
+        }
+    }
+    let shared = |p: u64| touches.get(&p).map_or(false, |&n| n > 1);
+    segs.sort_by_key(|s| s.addr);
+    let mut pieces: Vec<Seg> = Vec::new();
+    let mut shared_pages: Vec<Seg> = Vec::new();
+    for seg in segs {
+        let start = seg.aligned_start();
+        let end = seg.addr + seg.memsz as u64;
+        let bytes = |lo: u64, hi: u64| {
+            let i = usize::min((lo - start) as usize, seg.data.len());
+            let j = usize::min((hi - start) as usize, seg.data.len());
+            &seg.data[i..j]
+        };
+        let mut p = start;
+        while p < seg.aligned_end() {
+            if shared(p) {
+                let lo = u64::max(seg.addr, p);
+                let hi = u64::min(end, p + page);
+                let i = match shared_pages.iter().position(|s| s.addr == p) {
+                    Some(i) => i,
+                    None => {
+                        shared_pages.push(Seg {
+                            addr: p,
+                            memsz: PAGE_SIZE,
+                            perm: seg.perm,
+                            segtype: seg.segtype,
+                            data: vec![UNINITIALIZED_BYTE; PAGE_SIZE],
+                            module: seg.module,
+                        });
+                        shared_pages.len() - 1
+                    }
+                };
+                let shared_page = &mut shared_pages[i];
+                shared_page.perm |= seg.perm;
+                let src = bytes(lo, hi);
+                let dst = (lo - p) as usize;
+                shared_page.data[dst..dst + src.len()].copy_from_slice(src);
+                p += page;
+            } else {
+                /* a run of pages this segment has to itself */
+                let mut q = p;
+                while q < seg.aligned_end() && !shared(q) {
+                    q += page;
+                }
+                let mut piece = Seg {
+                    addr: u64::max(seg.addr, p),
+                    memsz: (u64::min(end, q) - u64::max(seg.addr, p)) as usize,
+                    perm: seg.perm,
+                    segtype: seg.segtype,
+                    data: bytes(p, q).to_vec(),
+                    module: seg.module,
+                };
+                piece.data.resize(piece.aligned_size(), UNINITIALIZED_BYTE);
+                pieces.push(piece);
+                p = q;
+            }
+        }
+    }
+    pieces.extend(shared_pages);
+    pieces.sort_by_key(|s| s.addr);
+    pieces
+}
+
+impl Display for Seg {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[aligned {:08x} -- {:08x}: {:?}]",
+            self.aligned_start(),
+            self.aligned_end(),
+            self.perm
+        )
     }
 }
 
@@ -738,76 +1631,852 @@ pub type MemImage<'a> = Vec<Seg>;
 lazy_static! {
     pub static ref MEM_IMAGE: MemImage<'static>
         = {
-            let obj = Object::parse(&CODE_BUFFER).unwrap();
             let mut segs: Vec<Seg> = Vec::new();
-            match obj {
-                Object::Elf(e) => {
-                    let mut page_one = false;
-                    let shdrs = &e.section_headers;
-                    let phdrs = &e.program_headers;
-                    for phdr in phdrs {
-                        let seg = Seg::from_phdr(&phdr);
-                        if seg.loadable() {
-                            let start = seg.aligned_start() as usize;
-                            if start == 0 { page_one = true };
-                            segs.push(seg);
+            if let Some(ref snapshot) = *SNAPSHOT {
+                /* the process had already loaded and relocated everything */
+                segs = snapshot.segs.clone();
+            } else if *RAW_BINARY {
+                /* no headers to go by, just the memory map in the config */
+                segs = raw_segments(&RAW_REGIONS);
+            } else {
+                let obj = Object::parse(&CODE_BUFFER).unwrap();
+                match obj {
+                    Object::Elf(e) => {
+                        segs.extend(elf_segments(&e, &CODE_BUFFER, 0, 0));
+                        /* Low memory */
+                        if !segs.iter().any(|s| s.aligned_start() == 0) {
+                            segs.push(Seg { addr: 0,
+                                            memsz: 0x1000,
+                                            perm: PROT_READ,
+                                            segtype: SegType::Load,
+                                            data: vec![0; 0x1000],
+                                            module: None,
+                            });
+                        };
+                    },
+                    Object::PE(pe) => {
+                        /* The headers are mapped read-only at the image base,
+                         * and each section at its RVA from there, as the
+                         * Windows loader would do if it didn't have to rebase. */
+                        let image_base = pe.image_base as u64;
+                        let mut pe_segs = Vec::new();
+                        if let Some(opt) = pe.header.optional_header {
+                            let size = opt.windows_fields.size_of_headers as usize;
+                            let mut seg = Seg { addr: image_base,
+                                                memsz: size,
+                                                perm: PROT_READ,
+                                                segtype: SegType::Load,
+                                                data: Vec::new(),
+                                                module: Some(0),
+                            };
+                            seg.data = vec![UNINITIALIZED_BYTE; seg.aligned_size()];
+                            let aj = usize::min(size, CODE_BUFFER.len());
+                            seg.data[..aj].copy_from_slice(&CODE_BUFFER[..aj]);
+                            pe_segs.push(seg);
+                        };
+                        for section in pe.sections.iter().filter(|s| s.virtual_size + s.size_of_raw_data > 0) {
+                            pe_segs.push(Seg::from_pe_section(section, image_base));
+                        }
+                        segs.extend(merge_shared_pages(pe_segs));
+                    },
+                    Object::Mach(mach) => {
+                        for segment in select_macho(mach).segments.iter() {
+                            /* __PAGEZERO is a guard region, there to catch
+                             * null pointers, and nothing to be mapped */
+                            if segment.initprot == VM_PROT_NONE || segment.vmsize == 0 {
+                                continue;
+                            };
+                            segs.push(Seg::from_macho_segment(segment));
                         }
+                    },
+                    _ => panic!("Not yet implemented."),
+                }
+            }
+            if SNAPSHOT.is_none() {
+                for (i, lib) in LIBRARIES.iter().enumerate() {
+                    match Object::parse(&lib.buffer) {
+                        Ok(Object::Elf(e)) => segs.extend(elf_segments(&e, &lib.buffer, lib.base, i + 1)),
+                        _ => panic!("Can't load {}: only ELF libraries are supported", lib.path),
                     }
-                    /* Low memory */
-                    if !page_one {
-                        segs.push(Seg { addr: 0,
-                                        memsz: 0x1000,
-                                        perm: PROT_READ,
-                                        segtype: SegType::Load,
-                                        data: vec![0; 0x1000],
-                        });
+                }
+                let num_stubs = UNRESOLVED_SYMBOLS.len();
+                if num_stubs > 0 {
+                    segs.push(stub_segment(&segs, num_stubs));
+                };
+                relocate(&mut segs, 0);
+            };
+            /* a bad stack is reported by check_stack(), before anything
+             * gets as far as emulating */
+            let _ = place_stack(&mut segs);
+            for seg in &segs {
+                crate::notice!("{}, data len: {:x}", seg, seg.data.len());
+            }
+            segs
+        };
+}
+
+/* Gives the payload a stack: with a snapshot, the dumped region holding
+ * its saved sp, and otherwise a fresh one, where the config puts it, or
+ * else just above everything else, leaving room for the image to slide
+ * up into if ASLR is enabled. */
+fn place_stack(segs: &mut Vec<Seg>) -> Result<(), String> {
+    if SNAPSHOT.is_some() && *SNAPSHOT_STACK {
+        let sp = snapshot_sp().ok_or("The snapshot has no saved stack pointer")?;
+        let seg = segs
+            .iter_mut()
+            .find(|s| s.aligned_start() <= sp && sp < s.aligned_end() && s.is_writeable())
+            .ok_or_else(|| {
+                format!(
+                    "No writeable region of the snapshot holds its sp, 0x{:x}",
+                    sp
+                )
+            })?;
+        seg.segtype = SegType::Stack;
+        return Ok(());
+    };
+    let mut bottom = 0;
+    for seg in segs.iter() {
+        let b = seg.aligned_end();
+        if b > bottom {
+            bottom = b
+        };
+    }
+    if *ASLR {
+        bottom += *MAX_SLIDE;
+    };
+    let mut stack = Seg {
+        addr: STACK_BASE.unwrap_or(bottom),
+        perm: PROT_READ | PROT_WRITE,
+        segtype: SegType::Stack,
+        memsz: *STACK_SIZE,
+        data: Vec::new(),
+        module: None,
+    };
+    if let Some(seg) = segs.iter().find(|s| {
+        s.aligned_start() < stack.aligned_end() && stack.aligned_start() < s.aligned_end()
+    }) {
+        return Err(format!("The stack at {} overlaps {}", stack, seg));
+    };
+    stack.data = vec![0; stack.aligned_size()];
+    segs.push(stack);
+    Ok(())
+}
+
+/// Says what's wrong with where the stack was to go, if anything, in
+/// which case MEM_IMAGE has none, and nothing can be emulated.
+pub fn check_stack() -> Result<(), String> {
+    if MEM_IMAGE.iter().any(|s| s.segtype == SegType::Stack) {
+        return Ok(());
+    };
+    place_stack(&mut MEM_IMAGE.to_vec())
+}
+
+/// Says why the image can't be slid, if ASLR is on and it can't. PE
+/// base relocations aren't applied, so a slid PE image would go on using
+/// absolute pointers into where it used to be.
+pub fn check_aslr() -> Result<(), String> {
+    if *ASLR && SNAPSHOT.is_none() && !*RAW_BINARY {
+        if let Ok(Object::PE(_)) = Object::parse(&CODE_BUFFER) {
+            return Err(
+                "ASLR isn't supported for PE images, whose base relocations \
+                        aren't applied: set enabled=false under [ASLR]"
+                    .to_string(),
+            );
+        };
+    };
+    Ok(())
+}
+
+/// The stack pointer saved in the snapshot, if there is one.
+pub fn snapshot_sp() -> Option<u64> {
+    let sp = match *ARCHITECTURE {
+        Arch::X86(Mode::Bits64) => "rsp",
+        Arch::X86(_) => "esp",
+        _ => "sp",
+    };
+    SNAPSHOT
+        .as_ref()?
+        .registers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(sp))
+        .map(|&(_, value)| value)
+}
+
+/* Maps the PT_LOAD segments of an ELF file, displaced by bias, and fills
+ * them in from its allocated sections. */
+fn elf_segments(e: &elf::Elf<'_>, buffer: &[u8], bias: u64, module: usize) -> Vec<Seg> {
+    let mut segs = Vec::new();
+    for phdr in &e.program_headers {
+        let mut seg = Seg::from_phdr(&phdr);
+        if seg.loadable() {
+            seg.addr += bias;
+            seg.module = Some(module);
+            segs.push(seg);
+        }
+    }
+    for shdr in &e.section_headers {
+        /* sections that aren't loaded would otherwise land at the bottom
+         * of the image, and .bss has nothing in the file to copy */
+        if !shdr.is_alloc() || shdr.sh_type == elf::section_header::SHT_NOBITS {
+            continue;
+        };
+        let (i, j) = (
+            shdr.sh_offset as usize,
+            (shdr.sh_offset + shdr.sh_size) as usize,
+        );
+        let aj = usize::min(j, buffer.len());
+        let sdata = &buffer[usize::min(i, aj)..aj];
+        let addr = shdr.sh_addr + bias;
+        /* find the appropriate segment */
+        for seg in segs.iter_mut() {
+            if addr >= seg.aligned_start() && addr < seg.aligned_end() {
+                let mut v_off = (addr - seg.aligned_start()) as usize;
+                for byte in sdata {
+                    if v_off >= seg.data.len() {
+                        crate::notice!(
+                            "[x] v_off 0x{:x} > seg.data.len() 0x{:x}. Look into this. Line {} of loader.rs.",
+                            v_off,
+                            seg.data.len(),
+                            line!()
+                        );
+                        break;
                     };
+                    seg.data[v_off] = *byte;
+                    v_off += 1;
+                }
+                break;
+            }
+        }
+    }
+    segs
+}
 
-                    for shdr in shdrs {
-                        let (i,j) = (shdr.sh_offset as usize,
-                                     (shdr.sh_offset+shdr.sh_size) as usize);
-                        let aj = usize::min(j, CODE_BUFFER.len());
-                        let sdata = CODE_BUFFER[i..aj].to_vec();
-                        /* find the appropriate segment */
-
-                        for seg in segs.iter_mut() {
-                            if shdr.sh_addr >= seg.aligned_start()
-                                && shdr.sh_addr < seg.aligned_end() {
-                                let mut v_off
-                                    = (shdr.sh_addr - seg.aligned_start()) as usize;
-                                for byte in sdata {
-                                    if v_off >= seg.data.len() {
-                                        println!("[x] v_off 0x{:x} > seg.data.len() 0x{:x}. Look into this. Line {} of loader.rs.", v_off, seg.data.len(), line!());
-                                        break;
-                                    };
-                                    seg.data[v_off] = byte;
-                                    v_off += 1;
-                                }
-                                break;
-                            }
-                        }
-                    }
-                    /* now allocate the stack */
-                    let mut bottom = 0;
-                    for seg in &segs {
-                        let b = seg.aligned_end();
-                        if b > bottom { bottom = b };
-                    }
-                    segs.push(Seg { addr: bottom,
-                                    perm: PROT_READ|PROT_WRITE,
-                                    segtype: SegType::Load,
-                                    memsz: STACK_SIZE,
-                                    data: vec![0; STACK_SIZE]
-                    });
+/// The memory and registers of a process, caught at some moment, to
+/// start emulating from.
+pub struct Snapshot {
+    pub segs: Vec<Seg>,
+    pub registers: Vec<(String, u64)>,
+}
 
-                },
-                _ => panic!("Not yet implemented."),
+/* user space ends well below here; anything above, like [vsyscall],
+ * belongs to the kernel, and isn't ours to emulate */
+const KERNEL_SPACE: u64 = 0xFFFF_8000_0000_0000;
+
+lazy_static! {
+    pub static ref SNAPSHOT: Option<Snapshot> = if SNAPSHOT_PATH.is_empty() {
+        None
+    } else if Path::new(&*SNAPSHOT_PATH).is_dir() {
+        Some(load_snapshot_dir(Path::new(&*SNAPSHOT_PATH)))
+    } else {
+        Some(load_core(&read_file(&SNAPSHOT_PATH)))
+    };
+}
+
+/// Register values to write before the initial state is saved.
+pub fn initial_registers() -> Vec<(String, u64)> {
+    match *SNAPSHOT {
+        Some(ref snapshot) => snapshot.registers.clone(),
+        None => Vec::new(),
+    }
+}
+
+/* Reads an ELF core file: its PT_LOAD segments are the process's memory,
+ * and the first NT_PRSTATUS note holds the registers of the thread that
+ * dumped it. */
+fn load_core(buffer: &[u8]) -> Snapshot {
+    let e = match Object::parse(buffer) {
+        Ok(Object::Elf(e)) if e.header.e_type == elf::header::ET_CORE => e,
+        _ => panic!(
+            "Snapshot {} is neither a directory nor an ELF core file",
+            &*SNAPSHOT_PATH
+        ),
+    };
+    let mut segs = Vec::new();
+    for phdr in e
+        .program_headers
+        .iter()
+        .filter(|p| p.p_type == elf::program_header::PT_LOAD)
+    {
+        if phdr.p_vaddr >= KERNEL_SPACE {
+            continue;
+        };
+        let mut seg = Seg::from_phdr(&phdr);
+        seg.module = None;
+        let i = usize::min(phdr.p_offset as usize, buffer.len());
+        let j = usize::min((phdr.p_offset + phdr.p_filesz) as usize, buffer.len());
+        let v_off = (seg.addr - seg.aligned_start()) as usize;
+        let j = usize::min(j, i + seg.data.len() - v_off);
+        seg.data[v_off..v_off + (j - i)].copy_from_slice(&buffer[i..j]);
+        segs.push(seg);
+    }
+    let mut registers = Vec::new();
+    if let Some(notes) = e.iter_note_headers(buffer) {
+        for note in notes.filter_map(|n| n.ok()) {
+            if note.n_type == elf::note::NT_PRSTATUS {
+                registers = prstatus_registers(e.header.e_machine, note.desc, e.little_endian);
+                break;
             }
-            for seg in &segs {
-                println!("{}, data len: {:x}", seg, seg.data.len());
+        }
+    };
+    Snapshot { segs, registers }
+}
+
+/* The general registers in a prstatus note, in the order the kernel's
+ * elf_gregset_t lays them out, named as unicorn names them. */
+fn prstatus_registers(machine: u16, desc: &[u8], little_endian: bool) -> Vec<(String, u64)> {
+    use goblin::elf::header::{EM_386, EM_AARCH64, EM_ARM, EM_X86_64};
+    let (offset, width, names): (usize, usize, &[&str]) = match machine {
+        EM_X86_64 => (
+            112,
+            8,
+            &[
+                "r15", "r14", "r13", "r12", "rbp", "rbx", "r11", "r10", "r9", "r8", "rax", "rcx",
+                "rdx", "rsi", "rdi", "", "rip", "cs", "eflags", "rsp", "ss", "fs_base", "gs_base",
+                "ds", "es", "fs", "gs",
+            ],
+        ),
+        EM_386 => (
+            72,
+            4,
+            &[
+                "ebx", "ecx", "edx", "esi", "edi", "ebp", "eax", "ds", "es", "fs", "gs", "", "eip",
+                "cs", "eflags", "esp", "ss",
+            ],
+        ),
+        EM_ARM => (
+            72,
+            4,
+            &[
+                "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "sb", "sl", "fp", "ip", "sp",
+                "lr", "pc", "cpsr",
+            ],
+        ),
+        EM_AARCH64 => (
+            112,
+            8,
+            &[
+                "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12",
+                "x13", "x14", "x15", "x16", "x17", "x18", "x19", "x20", "x21", "x22", "x23", "x24",
+                "x25", "x26", "x27", "x28", "x29", "x30", "sp", "pc",
+            ],
+        ),
+        _ => {
+            crate::notice!(
+                "[!] Can't read registers from a core file for machine {}",
+                machine
+            );
+            return Vec::new();
+        }
+    };
+    let push = |w: u64, &b: &u8| (w << 8) | u64::from(b);
+    names
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| !name.is_empty())
+        .filter_map(|(i, name)| {
+            let bytes = desc.get(offset + i * width..offset + (i + 1) * width)?;
+            let value = if little_endian {
+                bytes.iter().rev().fold(0, push)
+            } else {
+                bytes.iter().fold(0, push)
+            };
+            Some((name.to_string(), value))
+        })
+        .collect()
+}
+
+/* Parses a line of /proc/pid/maps, as far as the addresses and
+ * permissions: "7ffff7a00000-7ffff7bcd000 r-xp 00000000 08:01 1234 /lib/..." */
+fn parse_maps_line(line: &str) -> Option<(u64, u64, Perm)> {
+    let mut fields = line.split_whitespace();
+    let range = fields.next()?;
+    let perms = fields.next()?;
+    let dash = range.find('-')?;
+    let start = u64::from_str_radix(&range[..dash], 16).ok()?;
+    let end = u64::from_str_radix(&range[dash + 1..], 16).ok()?;
+    let mut perm = unicorn::Protection::NONE;
+    for c in perms.chars() {
+        match c {
+            'r' => perm |= PROT_READ,
+            'w' => perm |= PROT_WRITE,
+            'x' => perm |= PROT_EXEC,
+            _ => (),
+        }
+    }
+    Some((start, end, perm))
+}
+
+/* A snapshot directory holds a maps file, in the format of
+ * /proc/pid/maps, a dump of each region it lists, named for its range
+ * as written there (7ffff7a00000-7ffff7bcd000), and a registers file
+ * with a register name and value on each line. Regions with no dump
+ * start out zeroed. */
+fn load_snapshot_dir(dir: &Path) -> Snapshot {
+    let maps = std::fs::read_to_string(dir.join("maps"))
+        .unwrap_or_else(|_| panic!("No maps file in snapshot directory {:?}", dir));
+    let mut segs = Vec::new();
+    for line in maps.lines().filter(|l| !l.trim().is_empty()) {
+        let (start, end, perm) =
+            parse_maps_line(line).unwrap_or_else(|| panic!("Bad line in maps: {:?}", line));
+        if start >= KERNEL_SPACE {
+            continue;
+        };
+        let mut seg = Seg {
+            addr: start,
+            memsz: (end - start) as usize,
+            perm,
+            segtype: SegType::Load,
+            data: Vec::new(),
+            module: None,
+        };
+        seg.data = vec![UNINITIALIZED_BYTE; seg.aligned_size()];
+        let range = line.split_whitespace().next().unwrap();
+        if let Ok(dump) = std::fs::read(dir.join(range)) {
+            let len = usize::min(dump.len(), seg.memsz);
+            let v_off = (seg.addr - seg.aligned_start()) as usize;
+            seg.data[v_off..v_off + len].copy_from_slice(&dump[..len]);
+        };
+        segs.push(seg);
+    }
+    let registers = match std::fs::read_to_string(dir.join("registers")) {
+        Ok(text) => text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let name = fields.next()?;
+                let value = parse_number(fields.next()?);
+                Some((name.to_lowercase(), value))
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    Snapshot { segs, registers }
+}
+
+/* The ELF files in the image, with their contents and the displacement
+ * each was loaded at. */
+fn elf_modules() -> Vec<(elf::Elf<'static>, &'static [u8], u64)> {
+    /* a raw binary has no relocations of its own */
+    let main = if *RAW_BINARY {
+        None
+    } else {
+        Some((&*CODE_BUFFER, 0))
+    };
+    main.into_iter()
+        .chain(LIBRARIES.iter().map(|l| (&l.buffer, l.base)))
+        .filter_map(|(buffer, bias)| match Object::parse(buffer) {
+            Ok(Object::Elf(e)) => Some((e, buffer.as_slice(), bias)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RelocKind {
+    /* B + A */
+    Relative,
+    /* S + A, where the addend of a REL entry is already in place */
+    Absolute,
+    /* S, for a GOT entry or PLT slot */
+    Slot,
+}
+
+fn reloc_kind(machine: u16, r_type: u32) -> Option<RelocKind> {
+    use goblin::elf::header::{EM_386, EM_AARCH64, EM_ARM, EM_X86_64};
+    use goblin::elf::reloc::*;
+    match (machine, r_type) {
+        (EM_X86_64, R_X86_64_RELATIVE)
+        | (EM_386, R_386_RELATIVE)
+        | (EM_ARM, R_ARM_RELATIVE)
+        | (EM_AARCH64, R_AARCH64_RELATIVE) => Some(RelocKind::Relative),
+        (EM_X86_64, R_X86_64_64)
+        | (EM_386, R_386_32)
+        | (EM_ARM, R_ARM_ABS32)
+        | (EM_AARCH64, R_AARCH64_ABS64) => Some(RelocKind::Absolute),
+        (EM_X86_64, R_X86_64_GLOB_DAT)
+        | (EM_X86_64, R_X86_64_JUMP_SLOT)
+        | (EM_386, R_386_GLOB_DAT)
+        | (EM_386, R_386_JMP_SLOT)
+        | (EM_ARM, R_ARM_GLOB_DAT)
+        | (EM_ARM, R_ARM_JUMP_SLOT)
+        | (EM_AARCH64, R_AARCH64_GLOB_DAT)
+        | (EM_AARCH64, R_AARCH64_JUMP_SLOT) => Some(RelocKind::Slot),
+        _ => None,
+    }
+}
+
+/* The dynamic relocations of a file that we know how to apply. */
+fn dynamic_relocs<'a>(e: &'a elf::Elf<'a>) -> impl Iterator<Item = (elf::Reloc, RelocKind)> + 'a {
+    e.dynrelas
+        .iter()
+        .chain(e.dynrels.iter())
+        .chain(e.pltrelocs.iter())
+        .filter_map(move |r| reloc_kind(e.header.e_machine, r.r_type).map(|k| (r, k)))
+}
+
+/* The dynamic symbol a relocation refers to, if it's defined in this file,
+ * and its name. */
+fn reloc_symbol<'a>(e: &'a elf::Elf<'a>, r: &elf::Reloc) -> Option<(&'a str, Option<u64>)> {
+    let sym = e.dynsyms.get(r.r_sym)?;
+    let name = e.dynstrtab.get(sym.st_name)?.ok()?;
+    let defined = if sym.st_shndx != 0 && sym.st_value != 0 {
+        Some(sym.st_value)
+    } else {
+        None
+    };
+    Some((name, defined))
+}
+
+/* Whether a relocation refers to a weak symbol left undefined, which the
+ * dynamic linker resolves to 0, if nothing else defines it, rather than
+ * refusing to load. Code tests for that 0, as with __gmon_start__. */
+fn reloc_weak(e: &elf::Elf<'_>, r: &elf::Reloc) -> bool {
+    e.dynsyms.get(r.r_sym).map_or(false, |sym| {
+        sym.st_shndx == 0 && sym.st_bind() == elf::sym::STB_WEAK
+    })
+}
+
+lazy_static! {
+    /// The names of the symbols that relocations refer to, but that
+    /// neither the binary nor any of its libraries define. Each gets a
+    /// stub function that just returns, except for weak symbols, which
+    /// are left at 0.
+    pub static ref UNRESOLVED_SYMBOLS: Vec<String> = {
+        let modules = elf_modules();
+        let defined = exported_symbols(&modules);
+        let mut names = Vec::new();
+        for (e, _, _) in modules.iter() {
+            for (r, _) in dynamic_relocs(e) {
+                if let Some((name, None)) = reloc_symbol(e, &r) {
+                    if !name.is_empty() && !defined.contains_key(name) && !reloc_weak(e, &r) {
+                        names.push(name.to_string());
+                    }
+                }
             }
-            segs
+        }
+        names.sort();
+        names.dedup();
+        names
+    };
+}
+
+/* Every defined dynamic symbol, by name, at its loaded address. The first
+ * module to define a name wins, as with the dynamic linker. */
+fn exported_symbols(modules: &[(elf::Elf<'static>, &[u8], u64)]) -> HashMap<String, u64> {
+    let mut defined = HashMap::new();
+    for (e, _, bias) in modules.iter() {
+        for sym in e.dynsyms.iter() {
+            if sym.st_shndx == 0 || sym.st_value == 0 {
+                continue;
+            };
+            if let Some(Ok(name)) = e.dynstrtab.get(sym.st_name) {
+                defined
+                    .entry(name.to_string())
+                    .or_insert(sym.st_value + bias);
+            }
+        }
+    }
+    defined
+}
+
+const STUB_SIZE: usize = 8;
+
+/* A stub does nothing but return to its caller. */
+fn stub_code() -> Vec<u8> {
+    let mut code = match *ARCHITECTURE {
+        Arch::X86(_) => vec![0xC3],
+        Arch::Arm(_) => vec![0x1E, 0xFF, 0x2F, 0xE1], /* bx lr */
+        Arch::Arm64(_) => vec![0xC0, 0x03, 0x5F, 0xD6], /* ret */
+        arch => panic!("No stub functions for {:?}", arch),
+    };
+    /* pad with breakpoints, or zeroes */
+    let pad = if let Arch::X86(_) = *ARCHITECTURE {
+        0xCC
+    } else {
+        0
+    };
+    code.resize(STUB_SIZE, pad);
+    code
+}
+
+/* One stub for each unresolved symbol, above everything else. */
+fn stub_segment(segs: &[Seg], num_stubs: usize) -> Seg {
+    let addr = segs.iter().map(|s| s.aligned_end()).max().unwrap_or(0);
+    let mut seg = Seg {
+        addr,
+        memsz: num_stubs * STUB_SIZE,
+        perm: PROT_READ | PROT_EXEC,
+        segtype: SegType::Stubs,
+        data: Vec::new(),
+        module: None,
+    };
+    seg.data = stub_code().repeat(num_stubs);
+    seg.data.resize(seg.aligned_size(), 0);
+    seg
+}
+
+lazy_static! {
+    /// The stub functions standing in for unresolved symbols.
+    pub static ref STUBS: Vec<Symbol> = match MEM_IMAGE.iter().find(|s| s.segtype == SegType::Stubs) {
+        Some(seg) => UNRESOLVED_SYMBOLS
+            .iter()
+            .enumerate()
+            .map(|(i, name)| Symbol {
+                name: name.clone(),
+                addr: seg.addr + (i * STUB_SIZE) as u64,
+                size: STUB_SIZE as u64,
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+}
+
+fn write_seg_word(segs: &mut [Seg], addr: u64, width: usize, little_endian: bool, word: u64) {
+    let seg = segs
+        .iter_mut()
+        .find(|s| s.aligned_start() <= addr && addr < s.aligned_end());
+    if let Some(seg) = seg {
+        let off = (addr - seg.aligned_start()) as usize;
+        if off + width > seg.data.len() {
+            return;
+        };
+        for i in 0..width {
+            let shift = if little_endian { i } else { width - 1 - i } * 8;
+            seg.data[off + i] = (word >> shift) as u8;
+        }
+    };
+}
+
+/* A word of an ELF file, at the given virtual address, as it is in the
+ * file, before any relocation; zero if it's not in the file, as in .bss. */
+fn file_word(e: &elf::Elf<'_>, buffer: &[u8], vaddr: u64, width: usize) -> u64 {
+    let offset = e
+        .program_headers
+        .iter()
+        .filter(|ph| ph.p_type == elf::program_header::PT_LOAD)
+        .find(|ph| ph.p_vaddr <= vaddr && vaddr + width as u64 <= ph.p_vaddr + ph.p_filesz)
+        .map(|ph| (ph.p_offset + vaddr - ph.p_vaddr) as usize);
+    let bytes = match offset.and_then(|o| buffer.get(o..o + width)) {
+        Some(bytes) => bytes,
+        None => return 0,
+    };
+    let push = |w: u64, &b: &u8| (w << 8) | u64::from(b);
+    if e.little_endian {
+        bytes.iter().rev().fold(0, push)
+    } else {
+        bytes.iter().fold(0, push)
+    }
+}
+
+/* Applies the dynamic relocations of the binary and its libraries, so
+ * that the GOT and PLT point where they would in a real process: at the
+ * libraries' definitions, or else at the stubs, or at 0, for weak symbols
+ * that nothing defines. MIPS files are left as they are: their GOT is
+ * laid out by the dynamic section rather than by relocations, and isn't
+ * filled in here. The files are taken to
+ * have been moved up by slide, and the stubs to be wherever segs has them.
+ * Implicit addends are read from the files, so relocating again, with a
+ * new slide, gives the same result as relocating once. */
+fn relocate(segs: &mut [Seg], slide: u64) {
+    let modules = elf_modules();
+    let mut resolved = exported_symbols(&modules);
+    for addr in resolved.values_mut() {
+        *addr += slide;
+    }
+    if let Some(stubs) = segs.iter().find(|s| s.segtype == SegType::Stubs) {
+        for (i, name) in UNRESOLVED_SYMBOLS.iter().enumerate() {
+            resolved.insert(name.clone(), stubs.addr + (i * STUB_SIZE) as u64);
+        }
+    };
+    let mut applied = 0;
+    for (e, buffer, bias) in modules.iter() {
+        if e.header.e_machine == elf::header::EM_MIPS {
+            if slide == 0 {
+                crate::notice!(
+                    "[relocate()] MIPS relocations aren't supported, \
+                     leaving the GOT as it is in the file"
+                );
+            };
+            continue;
         };
+        let bias = bias + slide;
+        let width = if e.is_64 { 8 } else { 4 };
+        for (r, kind) in dynamic_relocs(e) {
+            let target = r.r_offset + bias;
+            let implicit = match kind {
+                RelocKind::Slot => 0,
+                _ => file_word(e, buffer, r.r_offset, width),
+            };
+            let addend = r.r_addend.map(|a| a as u64).unwrap_or(implicit);
+            let value = match kind {
+                RelocKind::Relative => bias.wrapping_add(addend),
+                _ => match reloc_symbol(e, &r) {
+                    Some((_, Some(value))) => value + bias,
+                    Some((name, None)) => match resolved.get(name) {
+                        Some(addr) => *addr,
+                        None if reloc_weak(e, &r) => 0,
+                        None => continue,
+                    },
+                    None => continue,
+                }
+                .wrapping_add(addend),
+            };
+            write_seg_word(segs, target, width, e.little_endian, value);
+            applied += 1;
+        }
+    }
+    if applied > 0 && slide == 0 {
+        crate::notice!(
+            "[relocate()] applied {} relocations, with {} stubs",
+            applied,
+            UNRESOLVED_SYMBOLS.len()
+        );
+    };
+}
+
+/// A file loaded into the memory image: the binary itself, at index 0,
+/// followed by the libraries listed in the `[Binary]` section.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Module {
+    pub name: String,
+    pub base: u64,
+    /* how likely gadgets are to be drawn from this module, relative
+     * to the others */
+    pub weight: f32,
+}
+
+lazy_static! {
+    pub static ref MODULES: Vec<Module> = {
+        let paths = std::iter::once((&*BINARY_PATH, *BINARY_WEIGHT))
+            .chain(LIBRARIES.iter().map(|l| (&l.path, l.weight)));
+        paths
+            .enumerate()
+            .map(|(i, (path, weight))| Module {
+                name: Path::new(path)
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_else(|| path.to_string()),
+                base: MEM_IMAGE
+                    .iter()
+                    .filter(|s| s.module == Some(i))
+                    .map(|s| s.aligned_start())
+                    .min()
+                    .unwrap_or(0),
+                weight,
+            })
+            .collect()
+    };
+}
+
+pub fn find_module(addr: u64) -> Option<&'static Module> {
+    find_static_seg(addr)
+        .and_then(|seg| seg.module)
+        .map(|m| &MODULES[m])
+}
+
+/// Describes an address as module+offset, if it lies in a loaded file.
+pub fn module_offset(addr: u64) -> Option<String> {
+    find_module(addr).map(|m| format!("{}+0x{:x}", m.name, addr - m.base))
+}
+
+/// A region of memory in the map of a raw binary: where it goes, how big
+/// it is, and, unless it starts out zeroed, where in the file its contents
+/// come from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawRegion {
+    pub addr: u64,
+    pub size: usize,
+    pub perm: Perm,
+    pub offset: Option<usize>,
+}
+
+/// Parses a memory map of the form `addr:size:perms[:offset]`, with the
+/// regions separated by commas or whitespace, as in
+/// `0x08000000:0x100000:rx:0, 0x20000000:0x20000:rw`.
+pub fn parse_regions(spec: &str) -> Vec<RawRegion> {
+    spec.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|r| !r.is_empty())
+        .map(|r| {
+            let fields = r.split(':').collect::<Vec<&str>>();
+            if fields.len() < 3 || fields.len() > 4 {
+                panic!("Bad region {:?}, expected addr:size:perms[:offset]", r);
+            };
+            let mut perm = unicorn::Protection::NONE;
+            for c in fields[2].chars() {
+                match c {
+                    'r' => perm |= PROT_READ,
+                    'w' => perm |= PROT_WRITE,
+                    'x' => perm |= PROT_EXEC,
+                    '-' => (),
+                    _ => panic!("Bad permissions {:?} in region {:?}", fields[2], r),
+                }
+            }
+            RawRegion {
+                addr: parse_number(fields[0]),
+                size: parse_number(fields[1]) as usize,
+                perm,
+                offset: fields.get(3).map(|o| parse_number(o) as usize),
+            }
+        })
+        .collect()
+}
+
+/* Without a memory map, the whole file is mapped at the base address. */
+fn raw_segments(spec: &str) -> Vec<Seg> {
+    let mut regions = parse_regions(spec);
+    if regions.is_empty() {
+        regions.push(RawRegion {
+            addr: *RAW_BASE,
+            size: CODE_BUFFER.len(),
+            perm: PROT_READ | PROT_WRITE | PROT_EXEC,
+            offset: Some(0),
+        });
+    };
+    regions
+        .iter()
+        .map(|region| {
+            let mut seg = Seg {
+                addr: region.addr,
+                memsz: region.size,
+                perm: region.perm,
+                segtype: SegType::Load,
+                data: Vec::new(),
+                module: Some(0),
+            };
+            seg.data = vec![UNINITIALIZED_BYTE; seg.aligned_size()];
+            if let Some(offset) = region.offset {
+                let i = usize::min(offset, CODE_BUFFER.len());
+                let j = usize::min(offset + region.size, CODE_BUFFER.len());
+                let v_off = (seg.addr - seg.aligned_start()) as usize;
+                seg.data[v_off..v_off + (j - i)].copy_from_slice(&CODE_BUFFER[i..j]);
+            };
+            crate::notice!("[raw_segments()] s = {}", seg);
+            seg
+        })
+        .collect()
+}
+
+/// Picks the Mach-O image to load: the binary itself, or, from a fat
+/// binary, the slice named by `fat_arch` in the `[Binary]` section.
+pub fn select_macho(mach: Mach<'_>) -> MachO<'_> {
+    match mach {
+        Mach::Binary(macho) => macho,
+        Mach::Fat(fat) => {
+            let wanted = match FAT_ARCH.as_str() {
+                "x86_64" => mach::cputype::CPU_TYPE_X86_64,
+                "i386" | "x86" => mach::cputype::CPU_TYPE_X86,
+                "arm64" | "aarch64" => mach::cputype::CPU_TYPE_ARM64,
+                "arm" => mach::cputype::CPU_TYPE_ARM,
+                name => panic!("Unknown fat_arch: {}", name),
+            };
+            let arches = fat.arches().expect("Failed to read fat binary header");
+            let i = arches
+                .iter()
+                .position(|a| a.cputype == wanted)
+                .unwrap_or_else(|| panic!("No {} slice in fat binary", &*FAT_ARCH));
+            fat.get(i).expect("Failed to parse Mach-O slice")
+        }
+    }
 }
 
 pub fn find_static_seg(addr: u64) -> Option<&'static Seg> {
@@ -843,6 +2512,168 @@ pub fn read_static_mem(addr: u64, size: usize) -> Option<Vec<u8>> {
     }
 }
 
+/// A named region of the binary: a function or object symbol, or a
+/// section.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u64,
+    pub size: u64,
+}
+
+impl Symbol {
+    pub fn contains(&self, addr: u64) -> bool {
+        self.addr <= addr && addr < self.addr + u64::max(self.size, 1)
+    }
+}
+
+lazy_static! {
+    /// Function symbols from the binary and its libraries, sorted by
+    /// address. For a PE binary, these are its exports, and its imports,
+    /// named dll!function and placed at their slots in the import
+    /// address table.
+    pub static ref SYMBOLS: Vec<Symbol> = {
+        let mut syms = Vec::new();
+        if let Ok(Object::PE(pe)) = Object::parse(&CODE_BUFFER) {
+            let image_base = pe.image_base as u64;
+            for export in pe.exports.iter() {
+                if let Some(name) = export.name {
+                    syms.push(Symbol {
+                        name: name.to_string(),
+                        addr: image_base + export.rva as u64,
+                        size: export.size as u64,
+                    });
+                }
+            }
+            for import in pe.imports.iter() {
+                syms.push(Symbol {
+                    name: format!("{}!{}", import.dll, import.name),
+                    addr: image_base + import.rva as u64,
+                    size: *ADDR_WIDTH as u64,
+                });
+            }
+        }
+        if let Ok(Object::Mach(mach)) = Object::parse(&CODE_BUFFER) {
+            for (name, nlist) in select_macho(mach).symbols().filter_map(|s| s.ok()) {
+                /* skip undefined symbols, and debugging entries */
+                if nlist.n_sect == 0 || nlist.is_stab() || nlist.n_value == 0 {
+                    continue;
+                };
+                syms.push(Symbol {
+                    name: name.to_string(),
+                    addr: nlist.n_value,
+                    size: 0,
+                });
+            }
+        }
+        let main = std::iter::once((&*CODE_BUFFER, 0));
+        for (buffer, bias) in main.chain(LIBRARIES.iter().map(|l| (&l.buffer, l.base))) {
+            if let Ok(Object::Elf(e)) = Object::parse(buffer) {
+                for (symtab, strtab) in &[(&e.syms, &e.strtab), (&e.dynsyms, &e.dynstrtab)] {
+                    for sym in symtab.iter() {
+                        if !sym.is_function() || sym.st_value == 0 {
+                            continue;
+                        };
+                        if let Some(Ok(name)) = strtab.get(sym.st_name) {
+                            syms.push(Symbol {
+                                name: name.to_string(),
+                                addr: sym.st_value + bias,
+                                size: sym.st_size,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        syms.extend(STUBS.iter().cloned());
+        syms.sort_by_key(|s| s.addr);
+        syms.dedup_by_key(|s| s.addr);
+        syms
+    };
+}
+
+lazy_static! {
+    /// Allocated sections from the binary, sorted by address.
+    pub static ref SECTIONS: Vec<Symbol> = {
+        let mut sections = Vec::new();
+        if let Ok(Object::PE(pe)) = Object::parse(&CODE_BUFFER) {
+            for section in pe.sections.iter() {
+                if let Ok(name) = section.name() {
+                    sections.push(Symbol {
+                        name: name.to_string(),
+                        addr: pe.image_base as u64 + u64::from(section.virtual_address),
+                        size: u64::from(section.virtual_size),
+                    });
+                }
+            }
+        }
+        if let Ok(Object::Mach(mach)) = Object::parse(&CODE_BUFFER) {
+            for segment in select_macho(mach).segments.iter() {
+                for (section, _) in segment.sections().unwrap_or_default() {
+                    if let (Ok(segname), Ok(sectname)) = (section.segname(), section.name()) {
+                        sections.push(Symbol {
+                            name: format!("{},{}", segname, sectname),
+                            addr: section.addr,
+                            size: section.size,
+                        });
+                    }
+                }
+            }
+        }
+        if let Ok(Object::Elf(e)) = Object::parse(&CODE_BUFFER) {
+            for shdr in e.section_headers.iter().filter(|s| s.is_alloc()) {
+                if let Some(Ok(name)) = e.shdr_strtab.get(shdr.sh_name) {
+                    sections.push(Symbol {
+                        name: name.to_string(),
+                        addr: shdr.sh_addr,
+                        size: shdr.sh_size,
+                    });
+                }
+            }
+        }
+        sections.sort_by_key(|s| s.addr);
+        sections
+    };
+}
+
+/* The last symbol starting at or below addr, if it covers addr. Symbols
+ * with a size of zero are taken to run up to the next symbol, but no
+ * further than the end of whichever of the sections holds them, and
+ * cover only their own address if none does. */
+fn lookup<'a>(table: &'a [Symbol], sections: &[Symbol], addr: u64) -> Option<&'a Symbol> {
+    let i = match table.binary_search_by_key(&addr, |s| s.addr) {
+        Ok(i) => i,
+        Err(0) => return None,
+        Err(i) => i - 1,
+    };
+    let sym = &table[i];
+    if sym.contains(addr) {
+        return Some(sym);
+    };
+    if sym.size > 0 {
+        return None;
+    };
+    sections
+        .iter()
+        .find(|s| s.contains(sym.addr))
+        .filter(|s| s.contains(addr))
+        .map(|_| sym)
+}
+
+pub fn find_symbol(addr: u64) -> Option<&'static Symbol> {
+    lookup(&SYMBOLS, &SECTIONS, addr)
+}
+
+pub fn find_section(addr: u64) -> Option<&'static Symbol> {
+    lookup(&SECTIONS, &[], addr)
+}
+
+/// Describes an address as symbol+offset, if we know of a symbol
+/// covering it.
+pub fn symbolize(addr: u64) -> Option<String> {
+    find_symbol(addr).map(|s| format!("{}+0x{:x}", s.name, addr - s.addr))
+}
+
 #[test]
 fn test_engine_new() {
     let _emu = Engine::new(*ARCHITECTURE);
@@ -865,6 +2696,50 @@ fn test_engine_reset() {
     }
 }
 
+#[test]
+fn test_restore_state() {
+    let mut emu = Engine::new(*ARCHITECTURE);
+    let (stack, size) = emu.find_stack();
+    /* straddle a page boundary */
+    let addr = stack + (size / 2) as u64 - 8;
+    emu.mem_write(addr, &[0x41; 16]).unwrap();
+    assert!(!emu.verify_restore());
+    emu.restore_state().unwrap();
+    assert!(emu.verify_restore());
+}
+
+/* cargo test bench_restore_state -- --ignored --nocapture */
+#[test]
+#[ignore]
+fn bench_restore_state() {
+    use std::time::Instant;
+    let mut emu = Engine::new(*ARCHITECTURE);
+    let (stack, size) = emu.find_stack();
+    let payload = vec![0x41; 0x100];
+    let rounds = 10000;
+
+    let start = Instant::now();
+    for _ in 0..rounds {
+        emu.mem_write(stack + (size / 2) as u64, &payload).unwrap();
+        emu.restore_all().unwrap();
+    }
+    let all = start.elapsed().as_secs_f64();
+
+    let start = Instant::now();
+    for _ in 0..rounds {
+        emu.mem_write(stack + (size / 2) as u64, &payload).unwrap();
+        emu.restore_state().unwrap();
+    }
+    let dirty = start.elapsed().as_secs_f64();
+
+    println!(
+        "[bench_restore_state] all writeable memory: {:.0}/s, dirty pages only: {:.0}/s",
+        rounds as f64 / all,
+        rounds as f64 / dirty
+    );
+    assert!(emu.verify_restore());
+}
+
 #[test]
 fn stress_test_unicorn_cpu_arm() {
     use rand::Rng;
@@ -893,7 +2768,7 @@ fn stress_test_unicorn_cpu_arm() {
 fn stress_test_unicorn_cpu_mips() {
     use rand::Rng;
     if let Arch::Mips(_) = *ARCHITECTURE {
-        let mode = unicorn::Mode::BIG_ENDIAN;
+        let (_, mode) = ARCHITECTURE.as_uc().unwrap();
         let uc = CpuMIPS::new(mode).expect("Failed to create CpuMIPS");
         let mem_image: MemImage = MEM_IMAGE.to_vec();
         for seg in mem_image {
@@ -992,6 +2867,57 @@ pub static ARM_REGISTERS: [RegisterARM; 16] = [
     RegisterARM::PC,
 ];
 
+/* x0 to x31 by their ABI names, and the pc */
+pub static RISCV_REGISTERS: [&str; 33] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6", "pc",
+];
+
+/* r0 to r31, then the special purpose registers a chain can steer */
+pub static PPC_REGISTERS: [&str; 37] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "r13", "r14",
+    "r15", "r16", "r17", "r18", "r19", "r20", "r21", "r22", "r23", "r24", "r25", "r26", "r27",
+    "r28", "r29", "r30", "r31", "pc", "lr", "ctr", "cr", "xer",
+];
+
+pub static ARM64_REGISTERS: [RegisterARM64; 34] = [
+    RegisterARM64::X0,
+    RegisterARM64::X1,
+    RegisterARM64::X2,
+    RegisterARM64::X3,
+    RegisterARM64::X4,
+    RegisterARM64::X5,
+    RegisterARM64::X6,
+    RegisterARM64::X7,
+    RegisterARM64::X8,
+    RegisterARM64::X9,
+    RegisterARM64::X10,
+    RegisterARM64::X11,
+    RegisterARM64::X12,
+    RegisterARM64::X13,
+    RegisterARM64::X14,
+    RegisterARM64::X15,
+    RegisterARM64::X16,
+    RegisterARM64::X17,
+    RegisterARM64::X18,
+    RegisterARM64::X19,
+    RegisterARM64::X20,
+    RegisterARM64::X21,
+    RegisterARM64::X22,
+    RegisterARM64::X23,
+    RegisterARM64::X24,
+    RegisterARM64::X25,
+    RegisterARM64::X26,
+    RegisterARM64::X27,
+    RegisterARM64::X28,
+    RegisterARM64::X29, /* frame pointer */
+    RegisterARM64::X30, /* link register */
+    RegisterARM64::SP,
+    RegisterARM64::PC,
+    RegisterARM64::NZCV,
+];
+
 pub static X86_64_REGISTERS: [RegisterX86; 17] = [
     RegisterX86::RAX,
     RegisterX86::RBX,
@@ -1033,23 +2959,365 @@ pub static X86_32_REGISTERS: [RegisterX86; 16] = [
 
 pub const ARM_ARM: Arch = Arch::Arm(Mode::Arm);
 pub const ARM_THUMB: Arch = Arch::Arm(Mode::Thumb);
-pub const STACK_SIZE: usize = 0x1000;
 pub const UNINITIALIZED_BYTE: u8 = 0x00;
 
 pub const PROT_READ: Perm = unicorn::Protection::READ;
 pub const PROT_EXEC: Perm = unicorn::Protection::EXEC;
+/* Mach-O vm_prot_t bits */
+const VM_PROT_NONE: u32 = 0;
+const VM_PROT_READ: u32 = 1;
+const VM_PROT_WRITE: u32 = 2;
+const VM_PROT_EXECUTE: u32 = 4;
 pub const PROT_WRITE: Perm = unicorn::Protection::WRITE;
 pub const X86_RET: u8 = 0xC3;
 
+pub const X86_RET_IMM16: u8 = 0xC2;
+pub const X86_RETF: u8 = 0xCB;
+pub const X86_RETF_IMM16: u8 = 0xCA;
+pub const X86_INT_SYSCALL: u32 = 0x80;
+/* the exception numbers unicorn passes to interrupt hooks for svc and
+ * for the MIPS syscall instruction */
+pub const ARM_EXCP_SWI: u32 = 2;
+pub const MIPS_EXCP_SYSCALL: u32 = 17;
+
+/* ret, ret imm16, retf and retf imm16, allowing for the rep prefix that
+ * compilers sometimes put in front of a ret. */
 fn x86_ret(b: &[u8]) -> bool {
-    b[0] == X86_RET
+    let opcode = b.iter().find(|&&x| x != 0xF2 && x != 0xF3);
+    match opcode {
+        Some(&X86_RET) | Some(&X86_RET_IMM16) | Some(&X86_RETF) | Some(&X86_RETF_IMM16) => true,
+        _ => false,
+    }
+}
+
+/// Aligns the address at which the chain is written to the stack, so
+/// that, once its first word has been returned to, the stack pointer
+/// is where a freshly called function would expect it under the C
+/// calling convention: the next word of the chain sits where the
+/// return address would, with 16-byte alignment just above it (cdecl
+/// on i386 and the SysV ABI on x86_64 agree on this).
+pub fn align_stack_entry(addr: u64) -> u64 {
+    match *ARCHITECTURE {
+        Arch::X86(_) => {
+            let w = *ADDR_WIDTH as u64;
+            ((addr + 2 * w) & !0xF) - 2 * w
+        }
+        _ => addr,
+    }
 }
-/* An ARM return is a pop with PC as one of the destination registers */
+/* An ARM return is a pop with PC as one of the destination registers,
+ * a single word popped into PC, or a bx lr. Any of these can take us
+ * into Thumb mode, if the low bit of the new PC is set. */
 fn arm_ret(w: &[u8]) -> bool {
-    w[3] & 0x0E == 0x06 &&
-    w[0] & 0x10 == 0x10 && /* The instruction is a pop instruction, */
-    w[1] & 0x80 == 0x80 /* and R15 is a destination register     */
+    let word = u32::from_le_bytes([w[0], w[1], w[2], w[3]]) & 0x0FFFFFFF;
+    (w[3] & 0x0E == 0x08 &&
+     w[2] & 0x10 == 0x10 && /* The instruction is a load multiple, */
+     w[1] & 0x80 == 0x80) /* and R15 is a destination register */
+        || word == ARM_POP_PC
+        || word == ARM_BX_LR
 }
+const ARM_POP_PC: u32 = 0x049DF004; /* ldr pc, [sp], #4 */
+const ARM_BX_LR: u32 = 0x012FFF1E;
+/* pop {..., pc}, bx lr, or the 32-bit pop.w {..., pc} */
 fn thumb_ret(w: &[u8]) -> bool {
-    w[0] & 0xF6 == 0xB4 && w[0] & 1 == 1
+    w[1] == 0xBD
+        || (w[1] == 0x47 && w[0] == 0x70)
+        || (w.len() >= 4 && w[1] == 0xE8 && w[0] == 0xBD && w[3] & 0x80 == 0x80)
+}
+/* AArch64 instructions are always little endian, even on a big endian
+ * data configuration. */
+fn arm64_word(w: &[u8]) -> u32 {
+    u32::from_le_bytes([w[0], w[1], w[2], w[3]])
+}
+/* Assembles an instruction word, or half word, from its bytes. */
+fn insn_word(bytes: &[u8], endian: Mode) -> u32 {
+    let push = |w: u32, &b: &u8| (w << 8) | u32::from(b);
+    if endian == Mode::Be {
+        bytes.iter().fold(0, push)
+    } else {
+        bytes.iter().rev().fold(0, push)
+    }
+}
+fn mips_word(w: &[u8], mode: Mode) -> u32 {
+    insn_word(&w[..4], mode)
+}
+/* jr $ra, with or without a hazard barrier hint */
+fn mips_ret(w: u32) -> bool {
+    w & 0xFFFFF83F == 0x03E00008
+}
+/* jr to any other register, or jalr */
+fn mips_indirect_jump(w: u32) -> bool {
+    (w & 0xFC1FF83F == 0x00000008 && !mips_ret(w)) || w & 0xFC1F003F == 0x00000009
+}
+/* ret, i.e. jalr x0, 0(ra), or its compressed form c.jr ra */
+fn riscv_ret(w: u32, size: u32) -> bool {
+    match size {
+        2 => w == 0x8082,
+        _ => w == 0x00008067,
+    }
+}
+/* jalr, c.jr and c.jalr through anything other than a plain return */
+fn riscv_indirect_jump(w: u32, size: u32) -> bool {
+    let indirect = match size {
+        /* c.jr and c.jalr, with rs1 != 0 (c.jalr with rs1 = 0 is c.ebreak) */
+        2 => (w & 0xF07F == 0x8002 || w & 0xF07F == 0x9002) && w & 0x0F80 != 0,
+        _ => w & 0x707F == 0x0067,
+    };
+    indirect && !riscv_ret(w, size)
+}
+/* blr */
+fn ppc_ret(w: u32, _size: u32) -> bool {
+    w == 0x4E800020
+}
+/* bctr, bctrl */
+fn ppc_indirect_jump(w: u32, _size: u32) -> bool {
+    w & 0xFFFFFFFE == 0x4E800420
+}
+/* ret {xN} */
+fn arm64_ret(w: u32) -> bool {
+    w & 0xFFFFFC1F == 0xD65F0000
+}
+/* br xN, blr xN */
+fn arm64_indirect_jump(w: u32) -> bool {
+    w & 0xFFFFFC1F == 0xD61F0000 || w & 0xFFFFFC1F == 0xD63F0000
+}
+
+#[test]
+fn test_arm64_inst_sp_delta() {
+    /* ldp x29, x30, [sp], #16 */
+    assert_eq!(arm64_inst_sp_delta(0xA8C17BFD), 16);
+    /* stp x29, x30, [sp, #-16]! */
+    assert_eq!(arm64_inst_sp_delta(0xA9BF7BFD), -16);
+    /* add sp, sp, #0x20 */
+    assert_eq!(arm64_inst_sp_delta(0x910083FF), 0x20);
+    /* ldr x19, [sp], #8 */
+    assert_eq!(arm64_inst_sp_delta(0xF84087F3), 8);
+    /* mov x0, x1 */
+    assert_eq!(arm64_inst_sp_delta(0xAA0103E0), 0);
+    assert!(arm64_ret(0xD65F03C0));
+    assert!(arm64_indirect_jump(0xD63F0200));
+    assert!(!arm64_ret(0xD63F0200));
+}
+
+#[test]
+fn test_mips_branches() {
+    /* jr $ra */
+    assert!(mips_ret(0x03E00008));
+    assert!(!mips_indirect_jump(0x03E00008));
+    /* jr $t9 */
+    assert!(mips_indirect_jump(0x03200008));
+    assert!(!mips_ret(0x03200008));
+    /* jalr $t9 */
+    assert!(mips_indirect_jump(0x0320F809));
+    /* addiu $sp, $sp, 32 */
+    assert!(!mips_ret(0x27BD0020) && !mips_indirect_jump(0x27BD0020));
+    assert_eq!(mips_word(&[0x03, 0xE0, 0x00, 0x08], Mode::Be), 0x03E00008);
+    assert_eq!(mips_word(&[0x08, 0x00, 0xE0, 0x03], Mode::Le), 0x03E00008);
+}
+
+#[test]
+fn test_riscv_and_ppc_branches() {
+    /* ret, c.jr ra */
+    assert!(riscv_ret(0x00008067, 4) && riscv_ret(0x8082, 2));
+    assert!(!riscv_indirect_jump(0x00008067, 4) && !riscv_indirect_jump(0x8082, 2));
+    /* jalr ra, 0(a5); c.jr a5; c.jalr a5 */
+    assert!(riscv_indirect_jump(0x000780E7, 4));
+    assert!(riscv_indirect_jump(0x8782, 2));
+    assert!(riscv_indirect_jump(0x9782, 2));
+    /* c.ebreak */
+    assert!(!riscv_indirect_jump(0x9002, 2));
+    /* blr, bctr, bctrl, blrl */
+    assert!(ppc_ret(0x4E800020, 4));
+    assert!(ppc_indirect_jump(0x4E800420, 4) && ppc_indirect_jump(0x4E800421, 4));
+    assert!(!ppc_ret(0x4E800021, 4) && !ppc_indirect_jump(0x4E800020, 4));
+}
+
+#[test]
+fn test_disassembly_only_archs() {
+    for arch in &[
+        Arch::RiscV(Mode::Bits64),
+        Arch::Ppc(Mode::Be),
+        Arch::Ppc64(Mode::Le),
+    ] {
+        assert!(!arch.emulable());
+        assert!(Engine::with_slide(*arch, 0).is_err());
+        assert_eq!(register_id(*arch, "sp"), None);
+    }
+    assert!(Arch::X86(Mode::Bits64).emulable());
+    assert_eq!(register_names(Arch::RiscV(Mode::Bits32))[2], "sp");
+    assert_eq!(register_names(Arch::Ppc(Mode::Be))[33], "lr");
+}
+
+#[test]
+fn test_x86_ret() {
+    assert!(x86_ret(&[0xC3]));
+    assert!(x86_ret(&[0xF3, 0xC3]));
+    assert!(x86_ret(&[0xC2, 0x08, 0x00]));
+    assert!(x86_ret(&[0xCB]));
+    assert!(x86_ret(&[0xCA, 0x04, 0x00]));
+    assert!(!x86_ret(&[0xFF, 0xE0]));
+    assert!(!x86_ret(&[0xCD, 0x80]));
+}
+
+#[test]
+fn test_arm_and_thumb_rets() {
+    assert!(arm_ret(&[0x10, 0x80, 0xBD, 0xE8])); /* pop {r4, pc} */
+    assert!(arm_ret(&[0x04, 0xF0, 0x9D, 0xE4])); /* ldr pc, [sp], #4 */
+    assert!(arm_ret(&[0x1E, 0xFF, 0x2F, 0xE1])); /* bx lr */
+    assert!(!arm_ret(&[0x10, 0x40, 0xBD, 0xE8])); /* pop {r4, lr} */
+    assert!(!arm_ret(&[0x13, 0xFF, 0x2F, 0xE1])); /* bx r3 */
+    assert!(thumb_ret(&[0x10, 0xBD])); /* pop {r4, pc} */
+    assert!(thumb_ret(&[0x70, 0x47])); /* bx lr */
+    assert!(thumb_ret(&[0xBD, 0xE8, 0xF0, 0x81])); /* pop.w {r4-r8, pc} */
+    assert!(!thumb_ret(&[0x10, 0xB5])); /* push {r4, lr} */
+    assert!(!thumb_ret(&[0x18, 0x47])); /* bx r3 */
+}
+
+#[test]
+fn test_parse_regions() {
+    let regions = parse_regions("0x08000000:0x100000:r-x:0,\n 0x20000000:0x20000:rw");
+    assert_eq!(regions.len(), 2);
+    assert_eq!(
+        regions[0],
+        RawRegion {
+            addr: 0x08000000,
+            size: 0x100000,
+            perm: PROT_READ | PROT_EXEC,
+            offset: Some(0),
+        }
+    );
+    assert_eq!(regions[1].perm, PROT_READ | PROT_WRITE);
+    assert_eq!(regions[1].offset, None);
+    assert!(parse_regions("").is_empty());
+}
+
+#[test]
+fn test_reloc_kind() {
+    use goblin::elf::header::{EM_386, EM_MIPS, EM_X86_64};
+    assert_eq!(reloc_kind(EM_X86_64, 8), Some(RelocKind::Relative));
+    assert_eq!(reloc_kind(EM_X86_64, 7), Some(RelocKind::Slot));
+    assert_eq!(reloc_kind(EM_386, 1), Some(RelocKind::Absolute));
+    assert_eq!(reloc_kind(EM_MIPS, 3), None);
+}
+
+#[test]
+fn test_parse_maps_line() {
+    assert_eq!(
+        parse_maps_line("7ffff7a00000-7ffff7bcd000 r-xp 00000000 08:01 1234 /lib/libc.so.6"),
+        Some((0x7ffff7a00000, 0x7ffff7bcd000, PROT_READ | PROT_EXEC))
+    );
+    assert_eq!(
+        parse_maps_line("00601000-00602000 rw-p 00001000 08:01 42"),
+        Some((0x601000, 0x602000, PROT_READ | PROT_WRITE))
+    );
+    assert_eq!(parse_maps_line("garbage"), None);
+}
+
+#[test]
+fn test_prstatus_registers() {
+    use goblin::elf::header::EM_386;
+    let mut desc = vec![0u8; 72 + 17 * 4];
+    desc[72 + 12 * 4] = 0x78; /* eip */
+    desc[72 + 12 * 4 + 3] = 0x08;
+    let regs = prstatus_registers(EM_386, &desc, true);
+    assert!(regs.contains(&("eip".to_string(), 0x08000078)));
+    assert_eq!(regs.len(), 16);
+}
+
+#[test]
+fn test_prstatus_segment_bases() {
+    use goblin::elf::header::EM_X86_64;
+    let mut desc = vec![0u8; 112 + 27 * 8];
+    desc[112 + 21 * 8 + 1] = 0x74; /* fs_base */
+    let regs = prstatus_registers(EM_X86_64, &desc, true);
+    assert!(regs.contains(&("fs_base".to_string(), 0x7400)));
+    assert_eq!(
+        msr_id(Arch::X86(Mode::Bits64), "fs_base"),
+        Some(0xC000_0100)
+    );
+    assert_eq!(msr_id(Arch::X86(Mode::Bits32), "fs_base"), None);
+}
+
+#[test]
+fn test_merge_shared_pages() {
+    let seg = |addr: u64, memsz: usize, perm: Perm, byte: u8| {
+        let mut s = Seg {
+            addr,
+            memsz,
+            perm,
+            segtype: SegType::Load,
+            data: Vec::new(),
+            module: Some(0),
+        };
+        s.data = vec![UNINITIALIZED_BYTE; s.aligned_size()];
+        let off = (addr & 0xFFF) as usize;
+        for b in &mut s.data[off..off + memsz] {
+            *b = byte;
+        }
+        s
+    };
+    /* headers, .text and .data at a SectionAlignment of 0x200, with .data
+     * running on into a page of its own */
+    let segs = vec![
+        seg(0x400400, 0x1000, PROT_READ | PROT_WRITE, 0xdd),
+        seg(0x400000, 0x200, PROT_READ, 0x4d),
+        seg(0x400200, 0x200, PROT_READ | PROT_EXEC, 0xcc),
+        seg(0x402000, 0x100, PROT_READ, 0x11),
+    ];
+    let merged = merge_shared_pages(segs);
+    assert_eq!(merged.len(), 3);
+    assert_eq!(merged[0].addr, 0x400000);
+    assert_eq!(merged[0].memsz, 0x1000);
+    assert_eq!(merged[0].perm, PROT_READ | PROT_WRITE | PROT_EXEC);
+    assert_eq!(merged[0].data.len(), 0x1000);
+    assert_eq!(merged[0].data[0x1ff], 0x4d);
+    assert_eq!(merged[0].data[0x200], 0xcc);
+    assert_eq!(merged[0].data[0xfff], 0xdd);
+    /* the rest of .data isn't made executable along with the shared page */
+    assert_eq!(merged[1].addr, 0x401000);
+    assert_eq!(merged[1].memsz, 0x400);
+    assert_eq!(merged[1].perm, PROT_READ | PROT_WRITE);
+    assert_eq!(merged[1].data.len(), 0x1000);
+    assert_eq!(merged[1].data[0x3ff], 0xdd);
+    assert_eq!(merged[2].addr, 0x402000);
+    assert_eq!(merged[2].memsz, 0x100);
+    assert_eq!(merged[2].data[0], 0x11);
+}
+
+#[test]
+fn test_lookup() {
+    let sym = |name: &str, addr: u64, size: u64| Symbol {
+        name: name.to_string(),
+        addr,
+        size,
+    };
+    let sections = vec![sym(".text", 0x1000, 0x100), sym(".data", 0x2000, 0x100)];
+    let symbols = vec![
+        sym("main", 0x1000, 0x20),
+        sym("_start", 0x1040, 0),
+        sym("helper", 0x1080, 0x10),
+        sym("_end", 0x10c0, 0),
+        sym("lonely", 0x3000, 0),
+    ];
+    let name = |addr| lookup(&symbols, &sections, addr).map(|s| s.name.as_str());
+    assert_eq!(name(0xfff), None);
+    assert_eq!(name(0x1010), Some("main"));
+    /* a gap after a sized symbol belongs to nobody */
+    assert_eq!(name(0x1030), None);
+    /* an unsized one runs up to the next symbol */
+    assert_eq!(name(0x1050), Some("_start"));
+    assert_eq!(name(0x107f), Some("_start"));
+    assert_eq!(name(0x1080), Some("helper"));
+    /* but not past the end of its section */
+    assert_eq!(name(0x10ff), Some("_end"));
+    assert_eq!(name(0x1100), None);
+    assert_eq!(name(0x2000), None);
+    /* and with no section to hold it, it covers only itself */
+    assert_eq!(name(0x3000), Some("lonely"));
+    assert_eq!(name(0x3001), None);
+    assert_eq!(
+        lookup(&sections, &[], 0x2080).map(|s| s.name.as_str()),
+        Some(".data")
+    );
+    assert_eq!(lookup(&sections, &[], 0x1800), None);
 }
@@ -3,11 +3,19 @@ use crate::par::statics::*;
 use rand::seq::IteratorRandom;
 use rand::Rng;
 
-fn mutate_arithmetic<R: Rng>(allele: &Allele, rng: &mut R) -> Allele {
+fn mutate_arithmetic<R: Rng>(allele: &Allele, slides: &[u64], rng: &mut R) -> Allele {
     /* start basic, add more options later */
-    let delta = rng.gen::<isize>() % 16;
-    //println!("[+] mutate_arithmetic: delta = {}", delta);
-    allele.add(delta)
+    /* a mutation that would put a bad byte in the payload is tried again,
+     * and given up on if it keeps doing so */
+    for _ in 0..MAX_REDRAWS {
+        let delta = rng.gen::<isize>() % 16;
+        //println!("[+] mutate_arithmetic: delta = {}", delta);
+        let mutant = allele.add(delta);
+        if mutant.is_clean(slides) {
+            return mutant;
+        }
+    }
+    *allele
 }
 /// One-point crossover, between two u64s, as bitvectors.
 fn onept_bits<R: Rng>(a: u64, b: u64, rng: &mut R) -> u64 {
@@ -93,6 +101,8 @@ where
         rng,
     );
     let sites = xbits_sites(xbits, bound, crossover_degree, &mut rng);
+    /* the parents share their problems, and so their slides */
+    let slides = packed_slides(mother.phenome.keys());
     let mut offspring = Vec::new();
     let parents = vec![mother, father];
     let mut i = 0;
@@ -105,6 +115,7 @@ where
         i += 1;
         let mut egg = p0.genome.alleles.clone();
         let sem = &p1.genome.alleles;
+        let mut mutations = Vec::new();
         for site in /*0..bound { // FIXME seeing if xbits help // */ sites.iter() {
             //let site = &site;
             let codon =
@@ -113,7 +124,8 @@ where
                 by chance, this isn't a limitation.
              */
                 if rng.gen::<f32>() < *POINTWISE_MUTATION_RATE {
-                    mutate_arithmetic(&sem[*site], &mut rng)
+                    mutations.push(*site);
+                    mutate_arithmetic(&sem[*site], &slides, &mut rng)
                 } else {
                     sem[*site]
                 };
@@ -131,7 +143,12 @@ where
          */
         if zygote.entry() != None {
             /* screen out the gadgetless */
-            offspring.push(Creature::new(zygote, 0));
+            let pedigree = Pedigree::crossover(
+                vec![p0.name.clone(), p1.name.clone()],
+                sites.clone(),
+                mutations,
+            );
+            offspring.push(Creature::with_pedigree(zygote, 0, pedigree));
         };
         /*
           if cfg!(debug_assertions) {
@@ -3,6 +3,7 @@ use rand::SeedableRng;
 use rand_isaac::isaac64::Isaac64Rng;
 use std::sync::mpsc::{Receiver, SyncSender};
 use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
 
 use crate::emu;
 use crate::fit;
@@ -33,6 +34,11 @@ pub fn pipeline(
     }
     spawn(move || {
         for (count, x) in rx.into_iter().enumerate() {
+            if *KILL_SWITCH.read().unwrap() {
+                log::restore_tty();
+                println!("[!] Kill switch thrown on {} pipeline. Concluding.", note);
+                log::shutdown(0);
+            }
             if limit == 0 || count < limit {
                 if txs.len() > 1 {
                     let mut tx_num = 1;
@@ -46,12 +52,14 @@ pub fn pipeline(
                 };
                 match txs[0].send(x) {
                     Err(e) => {
+                        log::restore_tty();
                         println!("[tx:0] {}: {:?}", note, e);
-                        std::process::exit(99);
+                        log::shutdown(99);
                     }
                     Ok(_k) => (), //println!("[tx:0] {} ok {:?}", note, _k),
                 }
             } else {
+                log::restore_tty();
                 println!(
                     "[!] Limit of {} on {} pipeline reached. Concluding.",
                     limit, note
@@ -59,7 +67,7 @@ pub fn pipeline(
                 for tx in txs.iter() {
                     drop(tx.to_owned())
                 }
-                std::process::exit(0);
+                log::shutdown(0);
             }
         }
     })
@@ -67,19 +75,35 @@ pub fn pipeline(
 
 #[allow(unused_variables)]
 pub fn evolution_pond() {
+    if !ARCHITECTURE.emulable() {
+        eprintln!(
+            "[!] {:?} binaries can be loaded and disassembled, but the bundled \
+             Unicorn can't emulate them, so there's nothing to evolve.",
+            *ARCHITECTURE
+        );
+        std::process::exit(1);
+    };
+    if let Err(e) = emu::loader::check_stack().and_then(|_| emu::loader::check_aslr()) {
+        eprintln!("[!] {}", e);
+        std::process::exit(1);
+    };
     let rng_seed = *RNG_SEED;
     let mut rng = Isaac64Rng::from_seed(rng_seed);
 
     println!("[>] spawning seeder");
-    let (seed_rx, seed_hdl) = gen::spawn_seeder(
-        *POPULATION_SIZE,
-        &vec![vec![1, 2]], /* fake problem set */
-    );
+    let problem_set = gen::slide_problems(&mut rng, &[vec![1, 2]] /* fake problem set */);
+    let (seed_rx, seed_hdl) = gen::spawn_seeder(*POPULATION_SIZE, &problem_set);
 
     //    let (refill_pond_tx, refill_pond_rx) = sync_channel(*CHANNEL_SIZE);
 
     println!("[>] spawning logger");
     let (logger_tx, logger_hdl) = log::spawn_logger(*POPULATION_SIZE / 10, *POPULATION_SIZE / 10);
+    if *DASHBOARD {
+        log::spawn_dashboard(Duration::from_millis(*DASHBOARD_REFRESH_MS as u64));
+    }
+    if *METRICS_ENABLED {
+        log::spawn_metrics_server(&*METRICS_ADDRESS);
+    }
     println!("[>] spawning hatchery");
     let (hatch_tx, hatch_rx, hatch_hdl) = emu::spawn_hatchery(*NUM_ENGINES);
     println!("[>] spawning evaluator");
@@ -7,6 +7,7 @@ use rand_isaac::isaac64::Isaac64Rng;
 
 use crate::evo::crossover::homologous_crossover;
 use crate::gen::phenotype::{Creature, FitnessOps};
+use crate::par::counters;
 use crate::par::statics::*;
 
 pub fn spawn_breeder(
@@ -30,6 +31,7 @@ pub fn spawn_breeder(
             if sel_window.len() >= window_size {
                 // causing SendError on eval/log,breed //
                 let mut offspring = tournament(&mut sel_window, rng_seed);
+                counters::bump(&counters::TOURNAMENTS);
                 while !sel_window.is_empty() {
                     match sel_window.pop() {
                         Some(outgoing) => match from_breeder_tx.send(outgoing) {
@@ -42,7 +44,7 @@ pub fn spawn_breeder(
                 while !offspring.is_empty() {
                     match offspring.pop() {
                         Some(outgoing) => match hatch_tx.send(outgoing) {
-                            Ok(_) => (),
+                            Ok(_) => counters::bump(&counters::BRED),
                             Err(e) => println!("Error sending to hatch_tx: {:?}", e),
                         },
                         None => panic!("unreachable??"),
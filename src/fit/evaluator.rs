@@ -4,6 +4,7 @@ use std::thread::{spawn, JoinHandle};
 
 use crate::circbuf::CircBuf;
 use crate::gen::*;
+use crate::par::counters;
 use crate::par::statics::*;
 
 // use ketos::{Interpreter,FromValueRef};
@@ -91,16 +92,42 @@ fn slave_eval(
         //let f = interp.call("eval-fitness",
         //                    (creature).into()).unwrap();
         //let fit = f32::from_value_ref(&f).unwrap();
-        creature.fitness = Some(vec![
+        let fitness = vec![
             /* Here's where you list the fitness scores */
             creature.phenome.ff_mean_uniq_retcount(),
             creature.phenome.ff_mean_retcount(),
             creature.phenome.ff_mean_writecount(),
-        ]);
+        ];
+        let fitness = penalise(fitness, &creature);
+        creature.set_fitness(fitness);
         assert!(creature.has_hatched());
+        counters::bump(&counters::EVALUATED);
         eval_tx.send(creature).unwrap();
     }
 }
 /***
  * Various fitness functions, that can dispatches from slave_eval.
  */
+
+/* Scales every objective down for the ways in which the chain falls short
+ * of what the exploit allows. */
+fn penalise(fitness: Fitness, creature: &Creature) -> Fitness {
+    let payload_len = creature.genome.pack(&[]).len();
+    let overflow = creature.phenome.overflow_fraction(payload_len);
+    let accidents = creature.phenome.fault_rate(|kind| !kind.is_controlled());
+    let mut factor = (1.0 - *OVERFLOW_PENALTY * overflow) * (1.0 - *CRASH_PENALTY * accidents);
+    /* the payload differs from problem to problem, with its input slots */
+    let violations = creature
+        .phenome
+        .keys()
+        .map(|input| creature.genome.violations(input))
+        .max()
+        .unwrap_or(0);
+    if violations > 0 {
+        match *ON_VIOLATION {
+            OnViolation::Reject => factor = 0.0,
+            OnViolation::Penalise => factor *= (1.0 - *VIOLATION_PENALTY).powi(violations as i32),
+        }
+    }
+    fitness.iter().map(|f| f * factor).collect()
+}
@@ -1,11 +1,15 @@
 use rand;
 
-use crate::emu::loader::{align_inst_addr, find_static_seg, Mode, Seg, MEM_IMAGE};
+use crate::emu::loader::{
+    align_inst_addr, calc_sp_delta, find_static_seg, module_offset, Arch, Mode, Seg, SegType,
+    MEM_IMAGE, MODULES,
+};
 use crate::par::statics::*;
 use std::collections::HashMap;
 use std::fmt;
 use std::fmt::Display;
 
+use self::rand::distributions::WeightedIndex;
 use self::rand::Rng;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -17,6 +21,15 @@ pub struct Gadget {
 }
 
 impl Gadget {
+    /// The word that goes on the stack for this gadget. Jumps to Thumb
+    /// addresses are indicated by a LSB of 1.
+    pub fn word(&self) -> u64 {
+        match self.mode {
+            Mode::Thumb => self.entry | 1,
+            _ => self.entry,
+        }
+    }
+
     fn add(self, other: i64) -> Gadget {
         let seg = find_static_seg(self.entry);
         match seg {
@@ -26,13 +39,22 @@ impl Gadget {
                 let offset = self.entry as i64 - seg.addr as i64;
                 let new_offset = (offset + other) % seg.memsz as i64;
                 //println!("[+] seg.addr = 0x{:x}, offset = 0x{:x}, new_offset = 0x{:x}", seg.addr, offset, new_offset);
-                let new_entry = (seg.addr as i64 + new_offset) as u64;
+                /* On ARM, an odd addend flips the gadget between ARM and
+                 * Thumb, just as the low bit of a branch target would. */
+                let mode = match *ARCHITECTURE {
+                    Arch::Arm(_) if other % 2 != 0 => match self.mode {
+                        Mode::Thumb => Mode::Arm,
+                        _ => Mode::Thumb,
+                    },
+                    _ => self.mode,
+                };
+                let new_entry = align_inst_addr((seg.addr as i64 + new_offset) as u64, mode);
                 //println!("[+] Adding {} to gadget with entry 0x{:x} to create gadget with entry 0x{:x}", other, self.entry, new_entry);
                 Gadget {
                     ret_addr: self.ret_addr, /* TODO: Update ret_addr with analysis */
                     entry: new_entry,
-                    sp_delta: self.sp_delta, /* TODO: Update with analysis */
-                    mode: self.mode,         /* TODO: update if in ARM and other is odd */
+                    sp_delta: calc_sp_delta(new_entry, mode),
+                    mode,
                 }
             }
             None => {
@@ -44,14 +66,49 @@ impl Gadget {
 }
 //unsafe impl Send for Gadget {}
 
-pub const ENDIAN: Endian = Endian::Little;
+/* Each module's weight is shared out among its executable segments, so
+ * that a library with many segments isn't favoured for that alone. */
+fn seg_weights(segs: &[&Seg]) -> Vec<f32> {
+    segs.iter()
+        .map(|seg| match seg.module {
+            Some(m) => {
+                let n = segs.iter().filter(|s| s.module == Some(m)).count();
+                MODULES[m].weight / n as f32
+            }
+            None => 1.0,
+        })
+        .collect()
+}
+
+/* ARM targets get gadgets in both instruction sets. */
+fn seed_mode<R: Rng>(rng: &mut R) -> Mode {
+    match *ARCHITECTURE {
+        Arch::Arm(_) => {
+            if rng.gen::<bool>() {
+                Mode::Thumb
+            } else {
+                Mode::Arm
+            }
+        }
+        arch => arch.mode(),
+    }
+}
+
+lazy_static! {
+    /* the byte order in which chains are packed onto the stack */
+    pub static ref ENDIAN: Endian = match ARCHITECTURE.mode() {
+        Mode::Be => Endian::Big,
+        _ => Endian::Little,
+    };
+}
 
 impl Display for Gadget {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "[Entry: {}, Ret: {}, SpD: {:x}, Mode: {:?}]",
+            "[Entry: {} ({}), Ret: {}, SpD: {:x}, Mode: {:?}]",
             wf(self.entry),
+            module_offset(self.entry).unwrap_or_default(),
             wf(self.ret_addr),
             self.sp_delta,
             self.mode
@@ -80,6 +137,16 @@ impl Allele {
         }
     }
 
+    /// Whether the allele's word can go into the payload without putting
+    /// a bad byte there, at each of the slides it'll be packed with.
+    /// Input words are the problem's to choose.
+    pub fn is_clean(&self, slides: &[u64]) -> bool {
+        match *self {
+            Allele::Input(_) => true,
+            Allele::Gadget(g) => slides.iter().all(|s| is_clean_word(g.word() + s)),
+        }
+    }
+
     pub fn add(&self, addend: isize) -> Self {
         match *self {
             /* FIXME: Assuming limit of 256 input slots, but hardcoded... */
@@ -138,7 +205,7 @@ impl Chain {
             /* Jumps to thumb addresses are indicated by a LSB of 1 */
             /* NB: Check to make sure Unicorn is actually following this */
             if gad.mode == Mode::Thumb { w |= 1 };
-            let wp = pack_word(w, *ADDR_WIDTH, ENDIAN);
+            let wp = pack_word(w, *ADDR_WIDTH, *ENDIAN);
             p.extend_from_slice(&wp);
             /* now, pack as many pads as needed to saturate sp_delta */
             if gad.sp_delta <= 1 { continue };
@@ -152,12 +219,20 @@ impl Chain {
                         input[o % input.len()]
                     } else { 0 },
                 };
-                let wp = pack_word(w, *ADDR_WIDTH, ENDIAN);
+                let wp = pack_word(w, *ADDR_WIDTH, *ENDIAN);
                 p.extend_from_slice(&wp);
             }
             pad_offset += gad.sp_delta-1;
         }
         */
+        /* Under ASLR, the gadgets are where the leak says the image is,
+         * just as an attacker would work them out, unless the chain is
+         * made to do that work itself. */
+        let slide = if *SLIDE_GADGETS {
+            split_slide(input).1
+        } else {
+            0
+        };
         let mut start = false;
         for allele in self.alleles.iter() {
             if allele.entry() == None && !start {
@@ -174,13 +249,25 @@ impl Chain {
                         0
                     }
                 }
-                Allele::Gadget(g) => g.entry,
+                Allele::Gadget(g) => g.word() + slide,
             };
-            p.extend_from_slice(&pack_word(w, *ADDR_WIDTH, ENDIAN));
+            p.extend_from_slice(&pack_word(w, *ADDR_WIDTH, *ENDIAN));
         }
         p
     }
 
+    /// How many ways the packed chain breaks the `[Constraints]`: one for
+    /// each bad byte, and one for each word past the size limit.
+    pub fn violations(&self, input: &[u64]) -> usize {
+        let packed = self.pack(input);
+        let excess = if *MAX_PAYLOAD_SIZE > 0 && packed.len() > *MAX_PAYLOAD_SIZE {
+            (packed.len() - *MAX_PAYLOAD_SIZE + *ADDR_WIDTH - 1) / *ADDR_WIDTH
+        } else {
+            0
+        };
+        count_bad_bytes(&packed, &BAD_BYTES) + excess
+    }
+
     pub fn entry(&self) -> Option<u64> {
         for allele in self.alleles.iter() {
             if let Some(e) = allele.entry() {
@@ -192,33 +279,64 @@ impl Chain {
         None
     }
 
+    /// The address to start emulation at: the entry of the first gadget,
+    /// with the Thumb bit set if need be, so that the CPU starts out in
+    /// the right mode.
+    pub fn start_addr(&self) -> Option<u64> {
+        self.alleles.iter().find_map(|allele| match *allele {
+            Allele::Gadget(g) => Some(g.word()),
+            _ => None,
+        })
+    }
+
+    fn seed_gadget<R: Rng>(
+        rng: &mut R,
+        exec_segs: &[&Seg],
+        seg_dist: &WeightedIndex<f32>,
+    ) -> Gadget {
+        let seg = &exec_segs[rng.sample(seg_dist)];
+        let unaligned_addr = seg.aligned_start() + rng.gen::<u64>() % seg.aligned_size() as u64;
+        let mode = seed_mode(rng);
+        let addr = align_inst_addr(unaligned_addr, mode);
+        Gadget {
+            entry: addr,
+            ret_addr: 0, /* TODO */
+            sp_delta: calc_sp_delta(addr, mode),
+            mode,
+        }
+    }
+
     /* TODO: create a separate thread that maintains the
      * pool of random seeds, and serves them on request,
      * over a channel, maybe.
      */
     /* TODO alignment function, which depends on ARCHITECTURE */
-    pub fn from_seed<R>(rng: &mut R, len_range: (usize, usize)) -> Self
+    pub fn from_seed<R>(rng: &mut R, len_range: (usize, usize), slides: &[u64]) -> Self
     where
         R: Rng,
     {
         let xbits: u64 = rng.gen::<u64>();
 
         let input_slot_freq = INPUT_SLOT_FREQ;
+        /* The stubs are executable, but only so that calls into them
+         * land somewhere; there are no gadgets to be found in them, or
+         * on the stack. Snapshot regions have no module either, so it's
+         * the segment type, not the module, that rules them out. */
         let exec_segs = MEM_IMAGE
             .iter()
             .filter(|s| s.is_executable())
+            .filter(|s| s.segtype != SegType::Stubs && s.segtype != SegType::Stack)
             .collect::<Vec<&Seg>>();
 
+        let seg_dist = WeightedIndex::new(seg_weights(&exec_segs))
+            .expect("No executable segment has a positive weight");
+
         let mut alleles: Vec<Allele> = Vec::new();
         let (min_len, max_len) = len_range;
         let range = usize::max(1, max_len - min_len);
         let glen = rng.gen::<usize>() % range + min_len;
 
         for _ in 0..glen {
-            let seg = &exec_segs[rng.gen::<usize>() % exec_segs.len()];
-            let unaligned_addr = seg.aligned_start() + rng.gen::<u64>() % seg.aligned_size() as u64;
-            let mode = ARCHITECTURE.mode(); /* choose mode randomly if ARM */
-            let addr = align_inst_addr(unaligned_addr, mode);
             /* sp_delta-informed chance of choosing const or input TODO */
             if !alleles.is_empty() && rng.gen::<f32>() < input_slot_freq {
                 /* NOTE: Artificially adding an upper bound on the number of inputs
@@ -227,13 +345,16 @@ impl Chain {
                  */
                 alleles.push(Allele::Input(rng.gen::<usize>() & 0x0F));
             } else {
-                let gad = Gadget {
-                    entry: addr,
-                    ret_addr: 0, /* TODO */
-                    sp_delta: 0, /* TODO */
-                    mode,        /* TODO - for ARM decide mode */
-                };
-
+                /* Draw again if the address would put a bad byte in the
+                 * payload. If there's no getting around it, the evaluator
+                 * will have its say. */
+                let mut gad = Self::seed_gadget(rng, &exec_segs, &seg_dist);
+                for _ in 0..MAX_REDRAWS {
+                    if Allele::Gadget(gad).is_clean(slides) {
+                        break;
+                    }
+                    gad = Self::seed_gadget(rng, &exec_segs, &seg_dist);
+                }
                 alleles.push(Allele::Gadget(gad));
             }
         }
@@ -281,16 +402,66 @@ impl Metadata {
     }
 }
 
+/* how many times to redraw a gadget address, or a mutation, that would put
+ * a bad byte in the payload, before letting it through */
+pub const MAX_REDRAWS: usize = 16;
+
+fn count_bad_bytes(bytes: &[u8], bad: &[u8]) -> usize {
+    bytes.iter().filter(|b| bad.contains(b)).count()
+}
+
+/// The slides that gadget words get packed with, across the given cases:
+/// just 0, unless ASLR is on and gadgets are packed slid.
+pub fn packed_slides<'a, I>(inputs: I) -> Vec<u64>
+where
+    I: IntoIterator<Item = &'a Vec<u64>>,
+{
+    let mut slides = if *SLIDE_GADGETS {
+        inputs
+            .into_iter()
+            .map(|input| split_slide(input).1)
+            .collect::<Vec<u64>>()
+    } else {
+        Vec::new()
+    };
+    slides.sort();
+    slides.dedup();
+    if slides.is_empty() {
+        slides.push(0);
+    };
+    slides
+}
+
+fn is_clean_word(word: u64) -> bool {
+    count_bad_bytes(&pack_word(word, *ADDR_WIDTH, *ENDIAN), &BAD_BYTES) == 0
+}
+
+/// Packs words as they'd sit on the stack, in the target's word size
+/// and byte order.
+pub fn pack_words(words: &[u64]) -> Vec<u8> {
+    words
+        .iter()
+        .flat_map(|w| pack_word(*w, *ADDR_WIDTH, *ENDIAN))
+        .collect()
+}
+
+/// Reads a packed payload back as words, ignoring any ragged end.
+pub fn unpack_words(bytes: &[u8]) -> Vec<u64> {
+    bytes.chunks_exact(*ADDR_WIDTH).map(unpack_word).collect()
+}
+
+/// Reads up to eight bytes as a number, in the target's byte order.
+pub fn unpack_word(bytes: &[u8]) -> u64 {
+    let fold = |w: u64, b: &u8| (w << 8) | *b as u64;
+    match *ENDIAN {
+        Endian::Big => bytes.iter().fold(0, fold),
+        Endian::Little => bytes.iter().rev().fold(0, fold),
+    }
+}
+
 fn pack_word(word: u64, size: usize, endian: Endian) -> Vec<u8> {
     let mut p = match size {
-        4 => {
-            let w32 = if endian == Endian::Big {
-                (word & 0xFFFFFFFF00000000) as u32
-            } else {
-                (word & 0x00000000FFFFFFFF) as u32
-            };
-            pack_word32le(w32)
-        }
+        4 => pack_word32le((word & 0x00000000FFFFFFFF) as u32),
         8 => pack_word64le(word),
         _ => panic!("Bad word size. Must be either 4 or 8."),
     };
@@ -336,3 +507,44 @@ pub fn pack_word64le_vec(v: &[u64]) -> Vec<u8> {
     }
     p
 }
+
+#[test]
+fn test_pack_word() {
+    assert_eq!(
+        pack_word(0xdeadbeef, 4, Endian::Big),
+        vec![0xde, 0xad, 0xbe, 0xef]
+    );
+    assert_eq!(
+        pack_word(0xdeadbeef, 4, Endian::Little),
+        vec![0xef, 0xbe, 0xad, 0xde]
+    );
+    assert_eq!(
+        pack_word(0x0102030405060708, 8, Endian::Big),
+        vec![1, 2, 3, 4, 5, 6, 7, 8]
+    );
+}
+
+#[test]
+fn test_unpack_words() {
+    let words = vec![0xdeadbeef, 0x1000, 0];
+    assert_eq!(unpack_words(&pack_words(&words)), words);
+}
+
+#[test]
+fn test_count_bad_bytes() {
+    let packed = pack_word(0x0040_0a10, 4, Endian::Little);
+    assert_eq!(count_bad_bytes(&packed, &[0x00, 0x0a]), 2);
+    assert_eq!(count_bad_bytes(&packed, &[]), 0);
+}
+
+#[test]
+fn test_packed_slides() {
+    let cases = vec![vec![7, 0x2000], vec![8, 0x1000], vec![9, 0x2000]];
+    let expected = if *ASLR && *SLIDE_GADGETS {
+        vec![0x1000, 0x2000]
+    } else {
+        vec![0]
+    };
+    assert_eq!(packed_slides(&cases), expected);
+    assert_eq!(packed_slides(&Vec::<Vec<u64>>::new()), vec![0]);
+}
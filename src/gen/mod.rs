@@ -10,3 +10,6 @@ pub use crate::phenotype::*;
 
 pub mod seeder;
 pub use crate::seeder::*;
+
+pub mod pedigree;
+pub use crate::pedigree::*;
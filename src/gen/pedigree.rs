@@ -0,0 +1,78 @@
+use std::fmt;
+use std::fmt::Display;
+
+use crate::phenotype::Fitness;
+
+/// The genetic operator that brought a creature into the world.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operator {
+    /// Generated from scratch by the seeder.
+    Seed,
+    /// Produced by homologous crossover. `sites` are the allele
+    /// positions copied over from the second parent, and `mutations`
+    /// the subset of those sites that were also mutated in transit.
+    Crossover {
+        sites: Vec<usize>,
+        mutations: Vec<usize>,
+    },
+}
+
+impl Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operator::Seed => write!(f, "seed"),
+            Operator::Crossover { sites, mutations } => {
+                write!(f, "crossover sites {:?} mutations {:?}", sites, mutations)
+            }
+        }
+    }
+}
+
+/* The pedigree is all that survives of a creature's parents once
+ * homologous_crossover returns. It's deliberately small, since the
+ * logger keeps one of these for every creature it ever sees.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pedigree {
+    pub parents: Vec<String>,
+    pub operator: Operator,
+    pub birth_fitness: Option<Fitness>,
+}
+
+impl Pedigree {
+    pub fn seed() -> Self {
+        Pedigree {
+            parents: Vec::new(),
+            operator: Operator::Seed,
+            birth_fitness: None,
+        }
+    }
+
+    pub fn crossover(parents: Vec<String>, sites: Vec<usize>, mutations: Vec<usize>) -> Self {
+        Pedigree {
+            parents,
+            operator: Operator::Crossover { sites, mutations },
+            birth_fitness: None,
+        }
+    }
+}
+
+impl Default for Pedigree {
+    fn default() -> Self {
+        Pedigree::seed()
+    }
+}
+
+impl Display for Pedigree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.parents.is_empty() {
+            write!(f, "{}", self.operator)?;
+        } else {
+            write!(f, "{} of {}", self.operator, self.parents.join(" and "))?;
+        }
+        match self.birth_fitness {
+            Some(ref fit) => write!(f, ", born with fitness {:?}", fit),
+            None => Ok(()),
+        }
+    }
+}
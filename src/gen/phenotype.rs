@@ -9,10 +9,11 @@ use std::sync::{Arc, Mutex};
 use rand::{Rng, SeedableRng};
 use rand_isaac::isaac64::Isaac64Rng;
 
-use crate::emu::loader::Mode;
+use crate::emu::loader::{BranchKind, Mode};
 use crate::genotype::*;
 use crate::log;
 use crate::par::statics::*;
+use crate::pedigree::Pedigree;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct WriteRecord {
@@ -22,6 +23,15 @@ pub struct WriteRecord {
     pub size: usize,
 }
 
+/// A read from memory: where from, by which instruction, and what it got.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReadRecord {
+    pub pc: u64,
+    pub src_addr: u64,
+    pub value: u64,
+    pub size: usize,
+}
+
 pub fn collapse_writelog(writelog: &[WriteRecord]) -> Vec<WriteRecord> {
     /* create order preserving set (?), keyed to address
     for each record, in order of execution, clobber any existing
@@ -40,6 +50,175 @@ pub fn collapse_writelog(writelog: &[WriteRecord]) -> Vec<WriteRecord> {
     result
 }
 
+/// A control transfer: where it came from, where it went, and how.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BranchRecord {
+    pub src: u64,
+    pub dst: u64,
+    pub kind: BranchKind,
+}
+
+impl Display for BranchRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {} [{:?}]", wf(self.src), wf(self.dst), self.kind)
+    }
+}
+
+/// A change of instruction set, on ARM, between two consecutive
+/// instructions: by way of a `bx`, a `blx`, or a `pop {pc}` with the
+/// low bit of the target set or cleared.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModeSwitch {
+    pub src: u64,
+    pub dst: u64,
+    pub mode: Mode,
+}
+
+impl Display for ModeSwitch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {} [{:?}]", wf(self.src), wf(self.dst), self.mode)
+    }
+}
+
+/// A system call made through a software interrupt (`int 0x80`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyscallRecord {
+    pub pc: u64,
+    pub number: u64,
+    pub args: Vec<u64>,
+}
+
+impl Display for SyscallRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}    [SYSCALL {}: {}]",
+            wf(self.pc),
+            self.number,
+            self.args
+                .iter()
+                .map(|a| format!("{:x}", a))
+                .collect::<Vec<String>>()
+                .join(" ")
+        )
+    }
+}
+
+/// Why the emulator stopped running a chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Termination {
+    /// ran through its whole instruction budget
+    BudgetExhausted,
+    /// ran out of wall-clock time
+    TimedOut,
+    /// stopped cleanly with budget and time to spare, as when the
+    /// emulator is stopped from a hook, or runs to the end address
+    Halted,
+    UnmappedRead,
+    UnmappedWrite,
+    UnmappedFetch,
+    /// touched mapped memory in a way its permissions don't allow
+    ProtectionFault,
+    InvalidInstruction,
+    /// made a system call, with `stop_at_syscall` set
+    Syscall,
+    /// reached the configured target address
+    ReachedTarget,
+    /// returned to the configured sentinel address
+    ReturnedToSentinel,
+    /// any other error from the emulator
+    Error,
+}
+
+impl Termination {
+    /// Whether the chain stopped by crashing, rather than running its
+    /// course or getting where it was meant to.
+    pub fn is_crash(&self) -> bool {
+        match self {
+            Termination::BudgetExhausted
+            | Termination::TimedOut
+            | Termination::Halted
+            | Termination::Syscall
+            | Termination::ReachedTarget
+            | Termination::ReturnedToSentinel => false,
+            _ => true,
+        }
+    }
+}
+
+impl Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Access {
+    Read,
+    Write,
+    Fetch,
+}
+
+/// What sort of crash a memory fault amounts to, as far as exploitation
+/// goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CrashKind {
+    /// the pc went to an address that came from the payload
+    ControlledPc,
+    /// a write to an address that came from the payload
+    ControlledWrite,
+    /// a read from an address that came from the payload
+    ControlledRead,
+    /// an access to the first page of memory
+    NullDeref,
+    /// anything else
+    Wild,
+}
+
+impl CrashKind {
+    /// Whether the chain had a hand in where the fault happened, which
+    /// makes it interesting rather than an accident.
+    pub fn is_controlled(&self) -> bool {
+        match self {
+            CrashKind::ControlledPc | CrashKind::ControlledWrite | CrashKind::ControlledRead => {
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// An invalid memory access that stopped the emulator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FaultRecord {
+    pub pc: u64,
+    pub addr: u64,
+    pub size: usize,
+    pub access: Access,
+    /* true if nothing was mapped there, false if the permissions were wrong */
+    pub unmapped: bool,
+    pub kind: CrashKind,
+}
+
+impl Display for FaultRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {:?} of {} bytes at {} ({}) [{:?}]",
+            wf(self.pc),
+            self.access,
+            self.size,
+            wf(self.addr),
+            if self.unmapped {
+                "unmapped"
+            } else {
+                "protected"
+            },
+            self.kind
+        )
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct VisitRecord {
     pub pc: u64,
@@ -69,6 +248,17 @@ pub struct Pod {
     pub visited: Vec<VisitRecord>,
     pub writelog: Vec<WriteRecord>,
     pub retlog: Vec<u64>,
+    /* why the emulator stopped */
+    pub termination: Termination,
+    pub syscalls: Vec<SyscallRecord>,
+    pub branchlog: Vec<BranchRecord>,
+    pub mode_switches: Vec<ModeSwitch>,
+    /* how many bytes of the payload didn't fit on the stack */
+    pub payload_overflow: usize,
+    /* the reads made from the ranges in read_ranges, in order */
+    pub readlog: Vec<ReadRecord>,
+    /* the memory fault that stopped the emulator, if it was one */
+    pub fault: Option<FaultRecord>,
 }
 
 impl Pod {
@@ -83,9 +273,22 @@ impl Pod {
             visited,
             writelog: collapse_writelog(&writelog),
             retlog,
+            termination: Termination::BudgetExhausted,
+            syscalls: Vec::new(),
+            branchlog: Vec::new(),
+            mode_switches: Vec::new(),
+            payload_overflow: 0,
+            readlog: Vec::new(),
+            fault: None,
         }
     }
 
+    /// True if the emulator stopped on an error rather than running
+    /// its course.
+    pub fn crashed(&self) -> bool {
+        self.termination.is_crash()
+    }
+
     pub fn retlog(&self) -> &Vec<u64> {
         &self.retlog
     }
@@ -115,6 +318,10 @@ impl Pod {
     pub fn writelog_len(&self) -> usize {
         self.writelog.len()
     }
+
+    pub fn readlog_len(&self) -> usize {
+        self.readlog.len()
+    }
     /// Dump a vector of strings containing the disassembly
     /// of each address visited by the phenotype.
     pub fn disas_visited(&self) -> Vec<String> {
@@ -198,6 +405,12 @@ pub trait FitFuncs {
     fn ff_mean_uniq_retcount(&self) -> f32;
     fn ff_mean_retcount(&self) -> f32;
     fn ff_mean_writecount(&self) -> f32;
+    fn ff_mean_readcount(&self) -> f32;
+    fn overflow_fraction(&self, payload_len: usize) -> f32;
+    fn termination_rate(&self, termination: Termination) -> f32;
+    fn fault_rate<F>(&self, pred: F) -> f32
+    where
+        F: Fn(CrashKind) -> bool;
 }
 
 impl FitFuncs for Phenome {
@@ -249,6 +462,41 @@ impl FitFuncs for Phenome {
     fn ff_mean_writecount(&self) -> f32 {
         self.mean_podwise_fitness(Pod::writelog_len)
     }
+
+    fn ff_mean_readcount(&self) -> f32 {
+        self.mean_podwise_fitness(Pod::readlog_len)
+    }
+
+    /// The mean share of a payload of the given length that had to be
+    /// cut off to fit on the stack. The words set around the payload
+    /// count against it too, if they don't fit, so this is capped at 1.
+    fn overflow_fraction(&self, payload_len: usize) -> f32 {
+        if payload_len == 0 {
+            return 0.0;
+        };
+        f32::min(
+            1.0,
+            self.mean_podwise_fitness(|pod| pod.payload_overflow) / payload_len as f32,
+        )
+    }
+
+    /// The share of cases that stopped for the given reason.
+    fn termination_rate(&self, termination: Termination) -> f32 {
+        self.mean_podwise_fitness(|pod| (pod.termination == termination) as usize)
+    }
+
+    /// The share of cases that crashed on a memory fault of a kind that
+    /// satisfies the predicate. A fault on the way to the sentinel or the
+    /// target doesn't count.
+    fn fault_rate<F>(&self, pred: F) -> f32
+    where
+        F: Fn(CrashKind) -> bool,
+    {
+        self.mean_podwise_fitness(|pod| {
+            (pod.termination.is_crash() && pod.fault.as_ref().map_or(false, |f| pred(f.kind)))
+                as usize
+        })
+    }
 }
 
 pub trait Pareto {
@@ -277,6 +525,7 @@ pub struct Creature {
     pub metadata: Metadata,
     pub name: String,
     pub fitness: Option<Fitness>,
+    pub pedigree: Pedigree,
 }
 
 impl PartialEq for Creature {
@@ -291,8 +540,9 @@ impl Display for Creature {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "BIOGRAPHY OF {}\nGENOME:\n{}\nPHENOME:\n{}\n{}\n{:?}",
+            "BIOGRAPHY OF {}\nPEDIGREE: {}\nGENOME:\n{}\nPHENOME:\n{}\n{}\n{:?}",
             self.name,
+            self.pedigree,
             self.genome,
             self.disas_visited().join("\t\n"),
             self.dump_written().join("\t\n"),
@@ -336,9 +586,17 @@ impl Creature {
             metadata: Metadata::new(),
             name,
             fitness: None,
+            pedigree: Pedigree::seed(),
         }
     }
 
+    /// Like `new`, but records where the genome came from.
+    pub fn with_pedigree(genome: Chain, index: usize, pedigree: Pedigree) -> Self {
+        let mut creature = Creature::new(genome, index);
+        creature.pedigree = pedigree;
+        creature
+    }
+
     pub fn inherit_problems(&mut self, parent: &Creature) {
         let mut larval_phenome = Phenome::new();
         let mut has_input = false;
@@ -361,6 +619,15 @@ impl Creature {
         self.metadata.0.insert("ab_fit", ab_fit);
     }
 
+    /// Sets the fitness, remembering it as the birth fitness if this
+    /// is the first time the creature has been evaluated.
+    pub fn set_fitness(&mut self, fitness: Fitness) {
+        if self.pedigree.birth_fitness.is_none() {
+            self.pedigree.birth_fitness = Some(fitness.clone());
+        }
+        self.fitness = Some(fitness);
+    }
+
     pub fn pose_problem(&mut self, input: &Input) {
         self.phenome.insert(input.clone(), None);
     }
@@ -372,7 +639,7 @@ impl Creature {
                 continue;
             };
             dump.push(format!(
-                "ON INPUT {:?}, VISITED:\n\t{}\nRETS: {}",
+                "ON INPUT {:?}, VISITED:\n\t{}\nRETS: {}\nSTOPPED: {}",
                 input,
                 pod.as_ref().unwrap().disas_visited().join("\n\t"),
                 pod.as_ref()
@@ -381,7 +648,12 @@ impl Creature {
                     .iter()
                     .map(|x| wf(*x))
                     .collect::<Vec<String>>()
-                    .join(" ")
+                    .join(" "),
+                match pod.as_ref().unwrap().fault {
+                    Some(ref fault) =>
+                        format!("{} at {}", pod.as_ref().unwrap().termination, fault),
+                    None => format!("{}", pod.as_ref().unwrap().termination),
+                }
             ));
         }
         dump
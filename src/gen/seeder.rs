@@ -5,14 +5,16 @@ use std::thread::{spawn, JoinHandle};
 use rand::{Rng, SeedableRng};
 use rand_isaac::isaac64::Isaac64Rng;
 
+use crate::emu::loader::slide_fits;
 use crate::genotype::*;
+use crate::par::counters;
 use crate::par::statics::*;
 use crate::phenotype::*;
 
 pub fn new_creature<R: Rng>(rng: &mut R, problem_set: &[Vec<u64>], index: usize) -> Creature {
     /* create a Creature::from_seed function */
     let len_range = (*MIN_CREATURE_LENGTH, *MAX_CREATURE_LENGTH);
-    let genome = Chain::from_seed(rng, len_range);
+    let genome = Chain::from_seed(rng, len_range, &packed_slides(problem_set));
     let mut creature = Creature::new(genome, index);
     for problem in problem_set.iter() {
         creature.pose_problem(&problem);
@@ -22,6 +24,41 @@ pub fn new_creature<R: Rng>(rng: &mut R, problem_set: &[Vec<u64>], index: usize)
     creature
 }
 
+/// Under ASLR, poses each problem several times over, each time with a
+/// random slide for the image appended to its input words. Gadgets are
+/// packed at their slid addresses, unless `slide_gadgets` is off, but
+/// data addresses always have to be worked out from the slide.
+pub fn slide_problems<R: Rng>(rng: &mut R, problem_set: &[Vec<u64>]) -> Vec<Vec<u64>> {
+    if !*ASLR {
+        return problem_set.to_vec();
+    };
+    /* slides have to keep the image on page boundaries */
+    let align = u64::max(*SLIDE_ALIGN, 0x1000);
+    let steps = *MAX_SLIDE / align + 1;
+    let mut cases = Vec::new();
+    for problem in problem_set.iter() {
+        for _ in 0..*ASLR_SLIDES {
+            /* a slide that would run the image into the stack is no use */
+            let slide = (0..MAX_REDRAWS)
+                .map(|_| rng.gen_range(0, steps) * align)
+                .find(|slide| slide_fits(*slide));
+            match slide {
+                Some(slide) => {
+                    let mut case = problem.clone();
+                    case.push(slide);
+                    cases.push(case);
+                }
+                None => println!(
+                    "[!] Couldn't find a slide up to 0x{:x} that keeps clear of the stack",
+                    *MAX_SLIDE
+                ),
+            }
+        }
+    }
+    assert!(!cases.is_empty(), "No slides to pose the problems with");
+    cases
+}
+
 pub fn spawn_seeder(
     num_wanted: usize,
     problem_set: &[Vec<u64>],
@@ -39,7 +76,7 @@ pub fn spawn_seeder(
             let creature = new_creature(&mut rng, &problem_set, index);
             index += 1;
             match from_seeder_tx.send(creature) {
-                Ok(_) => (),
+                Ok(_) => counters::bump(&counters::SEEDED),
                 Err(_) => println!("[+] Sending error in seeder at index = {}", index),
             }
         }
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File};
+use std::io::Write;
+use std::path::Path;
+
+use crate::emu::loader::{find_section, find_symbol, symbolize, Mode};
+use crate::fit::CircBuf;
+use crate::gen::Creature;
+use crate::log::disas_static;
+use crate::par::statics::wf;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoverageMode {
+    Off,
+    /* aggregate over the logger's sliding window, afresh each time */
+    Window,
+    /* aggregate over every creature the logger receives */
+    Run,
+}
+
+impl CoverageMode {
+    pub fn from_name(name: &str) -> CoverageMode {
+        match name.to_lowercase().as_str() {
+            "off" | "none" | "false" => CoverageMode::Off,
+            "window" => CoverageMode::Window,
+            "run" => CoverageMode::Run,
+            _ => panic!("Unknown coverage mode: {}", name),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Hit {
+    count: usize,
+    mode: Mode,
+    inst_size: usize,
+}
+
+/// Per-address hit counts for the instructions executed by the
+/// population, aggregated from the `VisitRecord`s in their phenomes.
+#[derive(Default)]
+pub struct Coverage {
+    hits: HashMap<u64, Hit>,
+    pub num_creatures: usize,
+}
+
+/// Hit counts summed over a function or section.
+#[derive(Clone, Debug)]
+pub struct RegionCoverage {
+    pub name: String,
+    pub hits: usize,
+    pub addresses: usize,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage {
+            hits: HashMap::new(),
+            num_creatures: 0,
+        }
+    }
+
+    pub fn add_creature(&mut self, creature: &Creature) {
+        self.num_creatures += 1;
+        for pod in creature.phenome.values().filter_map(|p| p.as_ref()) {
+            for vrec in pod.visited.iter() {
+                let hit = self.hits.entry(vrec.pc).or_insert(Hit {
+                    count: 0,
+                    mode: vrec.mode,
+                    inst_size: vrec.inst_size,
+                });
+                hit.count += 1;
+            }
+        }
+    }
+
+    pub fn from_window(window: &CircBuf) -> Self {
+        let mut coverage = Coverage::new();
+        for creature in window.buf.iter() {
+            coverage.add_creature(creature);
+        }
+        coverage
+    }
+
+    pub fn total_hits(&self) -> usize {
+        self.hits.values().map(|h| h.count).sum()
+    }
+
+    pub fn num_addresses(&self) -> usize {
+        self.hits.len()
+    }
+
+    /// Address and hit count, busiest first.
+    pub fn hottest(&self) -> Vec<(u64, usize)> {
+        let mut hot = self
+            .hits
+            .iter()
+            .map(|(addr, hit)| (*addr, hit.count))
+            .collect::<Vec<(u64, usize)>>();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hot
+    }
+
+    fn by_region<F>(&self, region_of: F) -> Vec<RegionCoverage>
+    where
+        F: Fn(u64) -> String,
+    {
+        let mut regions: HashMap<String, RegionCoverage> = HashMap::new();
+        for (addr, hit) in self.hits.iter() {
+            let name = region_of(*addr);
+            let region = regions.entry(name.clone()).or_insert(RegionCoverage {
+                name,
+                hits: 0,
+                addresses: 0,
+            });
+            region.hits += hit.count;
+            region.addresses += 1;
+        }
+        let mut regions = regions.into_iter().map(|(_, r)| r).collect::<Vec<_>>();
+        regions.sort_by(|a, b| b.hits.cmp(&a.hits).then(a.name.cmp(&b.name)));
+        regions
+    }
+
+    pub fn by_function(&self) -> Vec<RegionCoverage> {
+        self.by_region(|addr| match find_symbol(addr) {
+            Some(sym) => sym.name.clone(),
+            None => "[unknown]".to_string(),
+        })
+    }
+
+    pub fn by_section(&self) -> Vec<RegionCoverage> {
+        self.by_region(|addr| match find_section(addr) {
+            Some(sec) => sec.name.clone(),
+            None => "[unknown]".to_string(),
+        })
+    }
+
+    fn annotate(&self, addr: u64) -> String {
+        let hit = &self.hits[&addr];
+        disas_static(addr, hit.inst_size, hit.mode, 1)
+    }
+
+    pub fn text_report(&self, max_lines: usize) -> String {
+        let mut report = String::new();
+        report.push_str(&format!(
+            "COVERAGE: {} creatures, {} instructions executed at {} distinct addresses\n\n",
+            self.num_creatures,
+            self.total_hits(),
+            self.num_addresses()
+        ));
+        report.push_str("BY SECTION\n");
+        for region in self.by_section() {
+            report.push_str(&format!(
+                "{:>12} {:>8}  {}\n",
+                region.hits, region.addresses, region.name
+            ));
+        }
+        report.push_str("\nBY FUNCTION\n");
+        for region in self.by_function().iter().take(max_lines) {
+            report.push_str(&format!(
+                "{:>12} {:>8}  {}\n",
+                region.hits, region.addresses, region.name
+            ));
+        }
+        report.push_str("\nHOTTEST ADDRESSES\n");
+        for (addr, count) in self.hottest().iter().take(max_lines) {
+            report.push_str(&format!(
+                "{:>12}  {:<32} {}\n",
+                count,
+                symbolize(*addr).unwrap_or_default(),
+                self.annotate(*addr)
+            ));
+        }
+        report
+    }
+
+    /// An annotated disassembly of every address hit, in address order,
+    /// each line shaded according to how often it was executed.
+    pub fn html_report(&self) -> String {
+        let max = self.hits.values().map(|h| h.count).max().unwrap_or(1) as f32;
+        let mut addrs = self.hits.keys().copied().collect::<Vec<u64>>();
+        addrs.sort();
+        let mut html = String::new();
+        html.push_str(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>ROPER coverage</title>\n\
+             <style>body{font-family:monospace} td{padding:0 1em} \
+             tr.fn td{font-weight:bold;padding-top:1em}</style></head><body>\n",
+        );
+        html.push_str(&format!(
+            "<p>{} creatures, {} instructions executed at {} distinct addresses</p>\n<table>\n",
+            self.num_creatures,
+            self.total_hits(),
+            self.num_addresses()
+        ));
+        let mut last_fn: Option<String> = None;
+        for addr in addrs {
+            let function = find_symbol(addr).map(|s| s.name.clone());
+            if function != last_fn {
+                html.push_str(&format!(
+                    "<tr class=\"fn\"><td colspan=\"3\">{}</td></tr>\n",
+                    escape_html(function.as_ref().map(|s| s.as_str()).unwrap_or("[unknown]"))
+                ));
+                last_fn = function;
+            }
+            let count = self.hits[&addr].count;
+            /* shade on a log scale, so that the long tail is still visible */
+            let heat = (count as f32).ln_1p() / max.ln_1p();
+            html.push_str(&format!(
+                "<tr style=\"background:rgba(255,64,0,{:.3})\"><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                heat,
+                wf(addr),
+                count,
+                escape_html(&self.annotate(addr))
+            ));
+        }
+        html.push_str("</table></body></html>\n");
+        html
+    }
+
+    /// Writes `coverage.txt` and `coverage.html` to `dir`.
+    pub fn write_reports(&self, dir: &str, max_lines: usize) -> std::io::Result<()> {
+        create_dir_all(dir)?;
+        let mut txt = File::create(Path::new(dir).join("coverage.txt"))?;
+        txt.write_all(self.text_report(max_lines).as_bytes())?;
+        let mut html = File::create(Path::new(dir).join("coverage.html"))?;
+        html.write_all(self.html_report().as_bytes())
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
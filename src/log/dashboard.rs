@@ -0,0 +1,298 @@
+use std::fs::{create_dir_all, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::monitor::MONITOR;
+use crate::par::counters;
+use crate::par::statics::*;
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const CURVE_WIDTH: usize = 64;
+const DISAS_LINES: usize = 16;
+
+/* Set while the dashboard owns the terminal, so that chatter from
+ * the loader and hatchery doesn't scribble over the screen. */
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref SAVED_TTY: Mutex<Option<String>> = Mutex::new(None);
+}
+
+/// Prints a line to stdout, unless the dashboard is drawing there.
+#[macro_export]
+macro_rules! notice {
+    ($($arg:tt)*) => {
+        if !$crate::log::dashboard::dashboard_active() {
+            println!($($arg)*);
+        }
+    }
+}
+
+/// True while the dashboard has the terminal.
+pub fn dashboard_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Renders a series as a one-line bar chart, scaled to its own range.
+pub fn sparkline(series: &[f32], width: usize) -> String {
+    let tail = &series[series.len().saturating_sub(width)..];
+    let finite = tail
+        .iter()
+        .cloned()
+        .filter(|x| x.is_finite())
+        .collect::<Vec<f32>>();
+    let lo = finite.iter().cloned().fold(std::f32::INFINITY, f32::min);
+    let hi = finite
+        .iter()
+        .cloned()
+        .fold(std::f32::NEG_INFINITY, f32::max);
+    tail.iter()
+        .map(|x| {
+            if !x.is_finite() {
+                ' '
+            } else if hi <= lo {
+                SPARKS[0]
+            } else {
+                let i = ((x - lo) / (hi - lo) * (SPARKS.len() - 1) as f32).round() as usize;
+                SPARKS[usize::min(i, SPARKS.len() - 1)]
+            }
+        })
+        .collect::<String>()
+}
+
+/* Each pipeline stage is represented by the counter it bumps when it
+ * passes a creature along. */
+fn stages() -> [(&'static str, &'static counters::Counter); 5] {
+    [
+        ("seeder", &counters::SEEDED),
+        ("hatchery", &counters::DISPATCHED),
+        ("carousel", &counters::EMULATED),
+        ("evaluator", &counters::EVALUATED),
+        ("breeder", &counters::BRED),
+    ]
+}
+
+struct Throughput {
+    last: Vec<usize>,
+    instant: Instant,
+}
+
+impl Throughput {
+    fn new() -> Self {
+        Throughput {
+            last: stages().iter().map(|(_, c)| counters::read(c)).collect(),
+            instant: Instant::now(),
+        }
+    }
+
+    fn rates(&mut self) -> Vec<(&'static str, usize, f32)> {
+        let elapsed = self.instant.elapsed().as_secs_f32();
+        self.instant = Instant::now();
+        let mut rates = Vec::new();
+        for (i, (name, counter)) in stages().iter().enumerate() {
+            let now = counters::read(counter);
+            rates.push((*name, now, (now - self.last[i]) as f32 / elapsed));
+            self.last[i] = now;
+        }
+        rates
+    }
+}
+
+fn occupancy() -> Vec<(&'static str, isize)> {
+    let c = |x: &counters::Counter| counters::read(x) as isize;
+    vec![
+        (
+            "in emulation",
+            c(&counters::DISPATCHED) - c(&counters::EMULATED),
+        ),
+        (
+            "awaiting evaluation",
+            c(&counters::EMULATED) + c(&counters::ALREADY_HATCHED) - c(&counters::EVALUATED),
+        ),
+        (
+            "logger backlog",
+            c(&counters::EVALUATED) - c(&counters::LOGGED),
+        ),
+    ]
+}
+
+fn render(throughput: &mut Throughput) -> String {
+    let monitor = MONITOR.read().unwrap();
+    let mut screen = String::new();
+    /* clear the screen and home the cursor */
+    screen.push_str("\x1b[2J\x1b[H");
+    screen.push_str(&format!(
+        "ROPER -- {} elapsed -- [d]ump champion  [k]ill run  [q]uit dashboard\n\n",
+        fmt_duration(monitor.started.elapsed())
+    ));
+
+    screen.push_str("FITNESS (best of window, per objective)\n");
+    for i in 0..monitor.num_objectives() {
+        let series = monitor.objective_series(i);
+        screen.push_str(&format!(
+            "  obj {:<2} {:>10.4} {}\n",
+            i,
+            series.last().copied().unwrap_or(std::f32::NAN),
+            sparkline(&series, CURVE_WIDTH)
+        ));
+    }
+
+    screen.push_str("\nSTATISTICS\n");
+    if let Some(latest) = monitor.latest() {
+        for (name, value) in latest.iter() {
+            screen.push_str(&format!(
+                "  {:<18} {:>10.4} {}\n",
+                name,
+                value,
+                sparkline(&monitor.series(name), CURVE_WIDTH)
+            ));
+        }
+    }
+
+    screen.push_str("\nTHROUGHPUT\n");
+    for (name, total, rate) in throughput.rates() {
+        screen.push_str(&format!("  {:<18} {:>10} {:>10.1}/s\n", name, total, rate));
+    }
+
+    screen.push_str("\nCHANNELS\n");
+    for (name, count) in occupancy() {
+        screen.push_str(&format!("  {:<20} {:>8}\n", name, count));
+    }
+
+    screen.push_str("\nCHAMPION\n");
+    match monitor.champion {
+        None => screen.push_str("  (none yet)\n"),
+        Some(ref champion) => {
+            screen.push_str(&format!(
+                "  {} gen {} fitness {:?}\n",
+                champion.name,
+                champion.generation(),
+                champion.fitness
+            ));
+            let pod = champion.phenome.values().filter_map(|p| p.as_ref()).next();
+            if let Some(pod) = pod {
+                for line in pod.disas_visited().iter().take(DISAS_LINES) {
+                    screen.push_str(&format!("  {}\n", line));
+                }
+            }
+        }
+    }
+    screen
+}
+
+fn fmt_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
+
+/// Writes the current champion's biography to the log directory.
+pub fn dump_champion() -> std::io::Result<Option<String>> {
+    let monitor = MONITOR.read().unwrap();
+    match monitor.champion {
+        None => Ok(None),
+        Some(ref champion) => {
+            create_dir_all(&*LOG_DIRECTORY)?;
+            let path = Path::new(&*LOG_DIRECTORY).join(format!("champion-{}.txt", champion.name));
+            let mut file = File::create(&path)?;
+            file.write_all(format!("{}\n", champion).as_bytes())?;
+            Ok(Some(path.to_string_lossy().to_string()))
+        }
+    }
+}
+
+/* We want single keystrokes, without echo, but don't want to pull in a
+ * terminal library just for that, so let stty do the work. */
+fn stty(args: &[&str]) -> Option<String> {
+    Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .ok()
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+}
+
+/// Spawns the dashboard, which redraws the terminal every `refresh`
+/// and listens for keystrokes on stdin. It runs until `q` is pressed
+/// or the kill switch is thrown.
+pub fn spawn_dashboard(refresh: Duration) -> JoinHandle<()> {
+    *SAVED_TTY.lock().unwrap() = stty(&["-g"]);
+    stty(&["-icanon", "-echo", "min", "1"]);
+    ACTIVE.store(true, Ordering::SeqCst);
+    let running = Arc::new(AtomicBool::new(true));
+    let message = Arc::new(Mutex::new(String::new()));
+
+    {
+        let running = running.clone();
+        let message = message.clone();
+        spawn(move || {
+            for byte in std::io::stdin().bytes() {
+                match byte {
+                    Ok(b'd') => {
+                        let msg = match dump_champion() {
+                            Ok(Some(path)) => format!("champion dumped to {}", path),
+                            Ok(None) => "no champion to dump yet".to_string(),
+                            Err(e) => format!("failed to dump champion: {:?}", e),
+                        };
+                        *message.lock().unwrap() = msg;
+                    }
+                    Ok(b'k') => {
+                        *KILL_SWITCH.write().unwrap() = true;
+                        *message.lock().unwrap() = "kill switch thrown".to_string();
+                    }
+                    Ok(b'q') => {
+                        running.store(false, Ordering::Relaxed);
+                        break;
+                    }
+                    Ok(_) => (),
+                    Err(_) => break,
+                }
+            }
+        });
+    }
+
+    spawn(move || {
+        let mut throughput = Throughput::new();
+        while running.load(Ordering::Relaxed) && !*KILL_SWITCH.read().unwrap() {
+            let mut screen = render(&mut throughput);
+            screen.push_str(&format!("\n{}\n", message.lock().unwrap()));
+            print!("{}", screen);
+            std::io::stdout().flush().unwrap();
+            sleep(refresh);
+        }
+        restore_tty();
+    })
+}
+
+/// Hands the terminal back in the mode it was in before the
+/// dashboard took it over. Does nothing if the dashboard never ran.
+pub fn restore_tty() {
+    if !ACTIVE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    match SAVED_TTY.lock().unwrap().take() {
+        Some(ref mode) if !mode.is_empty() => {
+            stty(&[mode.as_str()]);
+        }
+        _ => {
+            stty(&["sane"]);
+        }
+    }
+}
+
+/// Restores the terminal, then exits the process with `code`. Any
+/// path that ends the run while the dashboard may be up should
+/// leave through here.
+pub fn shutdown(code: i32) -> ! {
+    restore_tty();
+    std::process::exit(code)
+}
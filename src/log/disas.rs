@@ -2,7 +2,7 @@ use capstone::prelude::*;
 use capstone::Capstone;
 
 use crate::emu::loader;
-use crate::emu::loader::{Arch, Mode};
+use crate::emu::loader::{Arch, BranchKind, Mode};
 use crate::par::statics::ARCHITECTURE;
 
 #[inline]
@@ -12,6 +12,7 @@ pub fn x86_64_disassembler() -> &'static Capstone {
             Capstone::new()
                     .x86()
                     .mode(arch::x86::ArchMode::Mode64)
+                    .detail(true)
                     .build()
                     .expect("Failed to initialize X86_64_DISASSEMBLER")
         ));
@@ -26,6 +27,7 @@ pub fn x86_32_disassembler() -> &'static Capstone {
             Capstone::new()
                     .x86()
                     .mode(arch::x86::ArchMode::Mode32)
+                    .detail(true)
                     .build()
                     .expect("Failed to initialize X86_32_DISASSEMBLER")
         ));
@@ -40,6 +42,7 @@ pub fn arm_disassembler() -> &'static Capstone {
             Capstone::new()
                     .arm()
                     .mode(arch::arm::ArchMode::Arm)
+                    .detail(true)
                     .build()
                     .expect("Failed to initialize ARM_DISASSEMBLER")
         ));
@@ -54,6 +57,7 @@ pub fn thumb_disassembler() -> &'static Capstone {
             Capstone::new()
                     .arm()
                     .mode(arch::arm::ArchMode::Thumb)
+                    .detail(true)
                     .build()
                     .expect("Failed to initialize THUMB_DISASSEMBLER")
         ));
@@ -61,16 +65,142 @@ pub fn thumb_disassembler() -> &'static Capstone {
     THUMB_DISASSEMBLER.with(|&x| x) // Copy the 'static Capstone
 }
 
-pub fn disas(insts: &[u8], mode: Mode, num_insts: usize) -> String {
-    let arch = ARCHITECTURE.with_mode(mode);
+#[inline]
+pub fn arm64_disassembler() -> &'static Capstone {
+    thread_local! {
+        pub static ARM64_DISASSEMBLER: &'static Capstone = Box::leak(Box::new(
+            Capstone::new()
+                    .arm64()
+                    .mode(arch::arm64::ArchMode::Arm)
+                    .detail(true)
+                    .build()
+                    .expect("Failed to initialize ARM64_DISASSEMBLER")
+        ));
+    }
+    ARM64_DISASSEMBLER.with(|&x| x) // Copy the 'static Capstone
+}
+
+#[inline]
+pub fn mips_disassembler() -> &'static Capstone {
+    thread_local! {
+        pub static MIPS_DISASSEMBLER: &'static Capstone = Box::leak(Box::new(
+            Capstone::new()
+                    .mips()
+                    .mode(arch::mips::ArchMode::Mips32)
+                    .endian(capstone::Endian::Big)
+                    .detail(true)
+                    .build()
+                    .expect("Failed to initialize MIPS_DISASSEMBLER")
+        ));
+    }
+    MIPS_DISASSEMBLER.with(|&x| x) // Copy the 'static Capstone
+}
 
+#[inline]
+pub fn mipsel_disassembler() -> &'static Capstone {
+    thread_local! {
+        pub static MIPSEL_DISASSEMBLER: &'static Capstone = Box::leak(Box::new(
+            Capstone::new()
+                    .mips()
+                    .mode(arch::mips::ArchMode::Mips32)
+                    .endian(capstone::Endian::Little)
+                    .detail(true)
+                    .build()
+                    .expect("Failed to initialize MIPSEL_DISASSEMBLER")
+        ));
+    }
+    MIPSEL_DISASSEMBLER.with(|&x| x) // Copy the 'static Capstone
+}
+
+#[inline]
+pub fn ppc_disassembler() -> &'static Capstone {
+    thread_local! {
+        pub static PPC_DISASSEMBLER: &'static Capstone = Box::leak(Box::new(
+            Capstone::new()
+                    .ppc()
+                    .mode(arch::ppc::ArchMode::Mode32)
+                    .endian(capstone::Endian::Big)
+                    .detail(true)
+                    .build()
+                    .expect("Failed to initialize PPC_DISASSEMBLER")
+        ));
+    }
+    PPC_DISASSEMBLER.with(|&x| x) // Copy the 'static Capstone
+}
+
+#[inline]
+pub fn ppc64_disassembler() -> &'static Capstone {
+    thread_local! {
+        pub static PPC64_DISASSEMBLER: &'static Capstone = Box::leak(Box::new(
+            Capstone::new()
+                    .ppc()
+                    .mode(arch::ppc::ArchMode::Mode64)
+                    .endian(capstone::Endian::Big)
+                    .detail(true)
+                    .build()
+                    .expect("Failed to initialize PPC64_DISASSEMBLER")
+        ));
+    }
+    PPC64_DISASSEMBLER.with(|&x| x) // Copy the 'static Capstone
+}
+
+#[inline]
+pub fn ppc64le_disassembler() -> &'static Capstone {
+    thread_local! {
+        pub static PPC64LE_DISASSEMBLER: &'static Capstone = Box::leak(Box::new(
+            Capstone::new()
+                    .ppc()
+                    .mode(arch::ppc::ArchMode::Mode64)
+                    .endian(capstone::Endian::Little)
+                    .detail(true)
+                    .build()
+                    .expect("Failed to initialize PPC64LE_DISASSEMBLER")
+        ));
+    }
+    PPC64LE_DISASSEMBLER.with(|&x| x) // Copy the 'static Capstone
+}
+
+fn hex(insts: &[u8]) -> String {
+    insts
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/* capstone's generic instruction groups, as numbered in capstone.h */
+const CS_GRP_JUMP: u8 = 1;
+const CS_GRP_CALL: u8 = 2;
+const CS_GRP_RET: u8 = 3;
+const CS_GRP_IRET: u8 = 5;
+
+/* None if capstone can't handle the architecture at all. */
+fn disassembler(arch: Arch) -> Option<&'static Capstone> {
     let cs = match arch {
         Arch::X86(Mode::Bits64) => x86_64_disassembler(),
         Arch::X86(Mode::Bits32) => x86_32_disassembler(),
         Arch::Arm(Mode::Arm) => arm_disassembler(),
         Arch::Arm(Mode::Thumb) => thumb_disassembler(),
+        Arch::Arm64(_) => arm64_disassembler(),
+        Arch::Mips(Mode::Be) => mips_disassembler(),
+        Arch::Mips(_) => mipsel_disassembler(),
+        Arch::Ppc(_) => ppc_disassembler(),
+        Arch::Ppc64(Mode::Le) => ppc64le_disassembler(),
+        Arch::Ppc64(_) => ppc64_disassembler(),
+        /* capstone 4 has no RISC-V support */
+        Arch::RiscV(_) => return None,
         _ => panic!("not yet implemented"),
     };
+    Some(cs)
+}
+
+pub fn disas(insts: &[u8], mode: Mode, num_insts: usize) -> String {
+    let arch = ARCHITECTURE.with_mode(mode);
+
+    let cs = match disassembler(arch) {
+        Some(cs) => cs,
+        None => return hex(insts),
+    };
     if let Ok(dis) = cs.disasm_count(insts, 0, num_insts) {
         dis.iter()
             .map(|i| {
@@ -83,13 +213,46 @@ pub fn disas(insts: &[u8], mode: Mode, num_insts: usize) -> String {
             .collect::<Vec<String>>()
             .join("; ")
     } else {
-        insts
-            .iter()
-            .map(|x| format!("{:02x}", x))
-            .collect::<Vec<String>>()
-            .join(" ")
+        hex(insts)
     }
 }
+/// Classifies an instruction by the groups capstone puts it in, telling
+/// direct branches from those that take their target from a register
+/// or from memory.
+pub fn branch_kind(insts: &[u8], mode: Mode) -> Option<BranchKind> {
+    let cs = disassembler(ARCHITECTURE.with_mode(mode))?;
+    let dis = cs.disasm_count(insts, 0, 1).ok()?;
+    let insn = dis.iter().next()?;
+    let detail = cs.insn_detail(&insn).ok()?;
+    let groups = detail.groups().map(|g| g.0).collect::<Vec<u8>>();
+    let indirect = !immediate_operand(insn.op_str().unwrap_or(""));
+    if groups.contains(&CS_GRP_RET) || groups.contains(&CS_GRP_IRET) {
+        Some(BranchKind::Ret)
+    } else if groups.contains(&CS_GRP_CALL) {
+        Some(if indirect {
+            BranchKind::IndirectCall
+        } else {
+            BranchKind::Call
+        })
+    } else if groups.contains(&CS_GRP_JUMP) {
+        Some(if indirect {
+            BranchKind::IndirectJump
+        } else {
+            BranchKind::Jump
+        })
+    } else {
+        None
+    }
+}
+
+/* Whether the branch target, the last operand, is written as an
+ * immediate: 0x401000 on x86, MIPS and PowerPC, #0x8000 on ARM. */
+fn immediate_operand(op_str: &str) -> bool {
+    let target = op_str.rsplit(',').next().unwrap_or("").trim();
+    let target = target.trim_start_matches('#');
+    target.starts_with("0x") || target.starts_with("-0x") || target.parse::<i64>().is_ok()
+}
+
 /* There seem to have been some major API changes between capstone 0.0.4 and
  * the latest version. There may or may not be a reason to try to get this
  * disas stuff up to date.
@@ -131,8 +294,25 @@ pub fn disas_static(addr: u64, num_bytes: usize, mode: Mode, num_insts: usize) -
     let some_bytes = loader::read_static_mem(addr, num_bytes);
     if let Some(bytes) = some_bytes {
         //println!("STATIC: {:?}, {} bytes: {:?}", mode, size, bytes);
-        format!("{:08x}\t{}", addr, disas(&bytes, mode, num_insts))
+        format!(
+            "{:08x} {}\t{}",
+            addr,
+            loader::module_offset(addr).unwrap_or_default(),
+            disas(&bytes, mode, num_insts)
+        )
     } else {
         format!("[INVALID ADDRESS: {:08x}]", addr)
     }
 }
+
+#[test]
+fn test_immediate_operand() {
+    assert!(immediate_operand("0x401000"));
+    assert!(immediate_operand("#0x8000"));
+    assert!(immediate_operand("$a0, $zero, 0x10"));
+    assert!(!immediate_operand("qword ptr [rip + 0x200fe2]"));
+    assert!(!immediate_operand("rax"));
+    assert!(!immediate_operand("lr"));
+    assert!(!immediate_operand("$t9"));
+    assert!(!immediate_operand(""));
+}
@@ -0,0 +1,203 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{create_dir_all, File};
+use std::io::Write;
+use std::path::Path;
+
+use crate::gen::{Creature, Fitness, Operator, Pedigree};
+
+/// What the genealogy remembers about each creature it has seen.
+#[derive(Clone, Debug)]
+pub struct LineageRecord {
+    pub name: String,
+    pub generation: usize,
+    pub pedigree: Pedigree,
+    pub fitness: Option<Fitness>,
+}
+
+/* Creatures are forgotten by the rest of the system as soon as they
+ * die, so the logger keeps its own register of births, keyed by name.
+ * Since names are derived from the packed genome, clones of the same
+ * chain share a single entry. The register is pruned, now and then,
+ * down to the ancestry of the creatures still in view, so that it
+ * doesn't grow for as long as the run does.
+ */
+#[derive(Default)]
+pub struct Genealogy {
+    records: HashMap<String, LineageRecord>,
+}
+
+impl Genealogy {
+    pub fn new() -> Self {
+        Genealogy {
+            records: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn record(&mut self, creature: &Creature) {
+        let entry = self
+            .records
+            .entry(creature.name.clone())
+            .or_insert_with(|| LineageRecord {
+                name: creature.name.clone(),
+                generation: creature.generation(),
+                pedigree: creature.pedigree.clone(),
+                fitness: None,
+            });
+        entry.fitness = creature.fitness.clone();
+    }
+
+    /// Forgets every record that isn't within `max_depth` generations
+    /// of the ancestry of one of the `living`.
+    pub fn prune(&mut self, living: &[&str], max_depth: usize) {
+        let keep = living
+            .iter()
+            .flat_map(|name| self.ancestry(name, max_depth))
+            .map(|rec| rec.name.clone())
+            .collect::<HashSet<String>>();
+        self.records.retain(|name, _| keep.contains(name));
+    }
+
+    /// Collects the known ancestors of `name`, breadth first, going back
+    /// at most `max_depth` generations. The creature itself comes first.
+    pub fn ancestry(&self, name: &str, max_depth: usize) -> Vec<&LineageRecord> {
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        let mut ancestors = Vec::new();
+        queue.push_back((name, 0));
+        while let Some((name, depth)) = queue.pop_front() {
+            if !seen.insert(name) {
+                continue;
+            };
+            if let Some(rec) = self.records.get(name) {
+                ancestors.push(rec);
+                if depth < max_depth {
+                    for parent in rec.pedigree.parents.iter() {
+                        queue.push_back((parent.as_str(), depth + 1));
+                    }
+                }
+            }
+        }
+        ancestors
+    }
+
+    pub fn to_dot(&self, name: &str, max_depth: usize) -> String {
+        let ancestry = self.ancestry(name, max_depth);
+        let mut dot = String::new();
+        dot.push_str("digraph lineage {\n\trankdir=BT;\n\tnode [shape=box];\n");
+        for rec in ancestry.iter() {
+            dot.push_str(&format!(
+                "\t\"{}\" [label=\"{}\\ngen {}\\nborn {}\\nfit {}\"];\n",
+                rec.name,
+                rec.name,
+                rec.generation,
+                fmt_fitness(&rec.pedigree.birth_fitness),
+                fmt_fitness(&rec.fitness),
+            ));
+        }
+        for rec in ancestry.iter() {
+            for (i, parent) in rec.pedigree.parents.iter().enumerate() {
+                let label = match rec.pedigree.operator {
+                    Operator::Crossover {
+                        ref sites,
+                        ref mutations,
+                    } if i == 1 => format!("sites {:?}\\nmut {:?}", sites, mutations),
+                    _ => String::new(),
+                };
+                dot.push_str(&format!(
+                    "\t\"{}\" -> \"{}\" [label=\"{}\"];\n",
+                    parent, rec.name, label
+                ));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self, name: &str, max_depth: usize) -> String {
+        let nodes = self
+            .ancestry(name, max_depth)
+            .iter()
+            .map(|rec| {
+                let (sites, mutations) = match rec.pedigree.operator {
+                    Operator::Seed => (Vec::new(), Vec::new()),
+                    Operator::Crossover {
+                        ref sites,
+                        ref mutations,
+                    } => (sites.clone(), mutations.clone()),
+                };
+                format!(
+                    "{{\"name\": \"{}\", \"generation\": {}, \"parents\": [{}], \
+                     \"operator\": \"{}\", \"sites\": {:?}, \"mutations\": {:?}, \
+                     \"birth_fitness\": {}, \"fitness\": {}}}",
+                    rec.name,
+                    rec.generation,
+                    rec.pedigree
+                        .parents
+                        .iter()
+                        .map(|p| format!("\"{}\"", p))
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                    match rec.pedigree.operator {
+                        Operator::Seed => "seed",
+                        Operator::Crossover { .. } => "crossover",
+                    },
+                    sites,
+                    mutations,
+                    json_fitness(&rec.pedigree.birth_fitness),
+                    json_fitness(&rec.fitness),
+                )
+            })
+            .collect::<Vec<String>>();
+        format!(
+            "{{\"champion\": \"{}\", \"lineage\": [\n  {}\n]}}\n",
+            name,
+            nodes.join(",\n  ")
+        )
+    }
+
+    /// Writes the lineage of `name` to `<dir>/lineage-<name>.{dot,json}`.
+    pub fn export(&self, name: &str, max_depth: usize, dir: &str) -> std::io::Result<()> {
+        create_dir_all(dir)?;
+        let stem = Path::new(dir).join(format!("lineage-{}", name));
+        let mut dot = File::create(stem.with_extension("dot"))?;
+        dot.write_all(self.to_dot(name, max_depth).as_bytes())?;
+        let mut json = File::create(stem.with_extension("json"))?;
+        json.write_all(self.to_json(name, max_depth).as_bytes())
+    }
+}
+
+fn fmt_fitness(fitness: &Option<Fitness>) -> String {
+    match fitness {
+        None => "?".to_string(),
+        Some(fvec) => fvec
+            .iter()
+            .map(|x| format!("{:.3}", x))
+            .collect::<Vec<String>>()
+            .join(" "),
+    }
+}
+
+fn json_fitness(fitness: &Option<Fitness>) -> String {
+    match fitness {
+        None => "null".to_string(),
+        Some(fvec) => format!(
+            "[{}]",
+            fvec.iter()
+                .map(|x| if x.is_finite() {
+                    format!("{}", x)
+                } else {
+                    "null".to_string()
+                })
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+    }
+}
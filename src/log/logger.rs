@@ -1,56 +1,95 @@
+use std::fs::{create_dir_all, File};
+use std::io::Write;
+use std::path::Path;
 use std::sync::mpsc::{sync_channel, SyncSender};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread::{spawn, JoinHandle};
 
+use crate::coverage::{Coverage, CoverageMode};
 use crate::fit::CircBuf;
 use crate::gen::{Creature, FitnessOps};
+use crate::lineage::Genealogy;
+use crate::monitor;
+use crate::par::counters;
 use crate::par::statics::*;
+use crate::stats::{objective_maxima, parse_stats, Analyser};
 
-/* the statistical functions can be defined as methods on
-* CircBuf
+/* the statistical functions are defined in the stats module, and
+ * selected by name in the [Logging] section of the config.
+ */
 
-*/
+/* Rows go to stdout, unless the dashboard has the terminal, in which
+ * case they go to stats.tsv in the log directory. */
+fn emit(row: &str) {
+    lazy_static! {
+        static ref TSV: Option<Mutex<File>> = if *DASHBOARD {
+            create_dir_all(&*LOG_DIRECTORY).expect("Failed to create log directory");
+            let path = Path::new(&*LOG_DIRECTORY).join("stats.tsv");
+            Some(Mutex::new(
+                File::create(path).expect("Failed to create stats.tsv"),
+            ))
+        } else {
+            None
+        };
+    }
+    match *TSV {
+        Some(ref file) => file.lock().unwrap().write_all(row.as_bytes()).unwrap(),
+        None => print!("{}", row),
+    }
+}
 
-/* just print to stdout for now, we'll do the file bit later */
-fn log_stats(stats: &[(&'static str, f32)]) {
+fn log_stats(stats: &[(String, f32)]) {
     let mut row = String::new();
     let num_stats = stats.len();
     for (counter, (_name, stat)) in stats.iter().enumerate() {
         row.push_str(&format!("{:6.6}", stat));
-        if counter < num_stats {
+        if counter + 1 < num_stats {
             row.push_str("\t")
         } else {
             row.push_str("\n")
         }
     }
-    print!("{}", row)
+    emit(&row)
 }
 /* the point of passing a vector of pairs each time is just to make
   the logging code easier to read and maintain. The alternative is to
   pass the headers, explicitly, at the beginning, and then an unlabelled
   sequence of floats every subsequent time.
 */
-fn log_header(stats: &[(&'static str, f32)]) {
+fn log_header(stats: &[(String, f32)]) {
     let mut row = String::new();
     let num_stats = stats.len();
     for (counter, (name, _stat)) in stats.iter().enumerate() {
-        row.push_str(&name.to_string());
-        if counter < num_stats {
+        row.push_str(name);
+        if counter + 1 < num_stats {
             row.push_str("\t")
         } else {
             row.push_str("\n")
         }
     }
-    print!("{}", row)
+    emit(&row)
 }
 
-fn log(stats: &[(&'static str, f32)], counter: usize) {
-    if counter == 0 {
-        log_header(&stats)
+fn log(stats: &[(String, f32)], header: &mut Vec<String>) {
+    /* The number of per-objective columns isn't known until the first
+     * fitness vectors arrive, so print the header whenever it changes. */
+    let names = stats
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<String>>();
+    if names != *header {
+        log_header(&stats);
+        *header = names;
     };
     log_stats(&stats)
 }
 
+/* Coverage reports are written every period analyses, or never, if the
+ * period is 0. */
+fn coverage_due(analyses: usize, period: usize) -> bool {
+    period > 0 && analyses % period == 0
+}
+
 /// The logger sits at the receiving end of a one-way channel.
 /// It's best to send cloned data to it, since you won't get it back.
 pub fn spawn_logger(
@@ -64,55 +103,64 @@ pub fn spawn_logger(
 
     let (analyse_tx, analyse_rx) = sync_channel(*CHANNEL_SIZE);
 
+    let genealogy = Arc::new(RwLock::new(Genealogy::new()));
+
+    let coverage_mode = CoverageMode::from_name(&*COVERAGE);
+    let run_coverage = Arc::new(RwLock::new(Coverage::new()));
+
     let window = circbuf.clone();
+    let family_tree = genealogy.clone();
+    let coverage = run_coverage.clone();
     let _stat_handle = spawn(move || {
+        let mut analyser = Analyser::new(parse_stats(&*STATISTICS));
+        let mut header = Vec::new();
         let mut max_fitness = 0.0;
-        let mut max_gen = 0;
-        for (log_counter, _) in analyse_rx.into_iter().enumerate() {
+        let mut analyses: usize = 0;
+        for _ in analyse_rx {
             let window = window.read().unwrap();
-            /* TODO here is where the analyses will be dispatched from */
-            //println!("circbuf holds {}", window.buf.len());
-            let mut sum_fit = 0.0;
-            let mut sum_gen = 0.0;
-            let mut sum_len = 0;
-            let mut count = 0;
             for creature in window.buf.iter() {
                 assert!(creature.has_hatched());
                 match creature.fitness {
                     None => panic!("-- creature with no fitness in logger"),
                     Some(ref fvec) => {
-                        count += 1;
                         let fit = fvec.mean() as f32;
                         if fit > max_fitness {
-                            println!("[LOGGER] Fitness: {}\n{}", fit, &creature);
+                            if !*DASHBOARD {
+                                println!("[LOGGER] Fitness: {}\n{}", fit, &creature);
+                            };
+                            monitor::publish_champion(&creature);
                             max_fitness = fit;
+                            if *LINEAGE_DEPTH > 0 {
+                                let family_tree = family_tree.read().unwrap();
+                                if let Err(e) = family_tree.export(
+                                    &creature.name,
+                                    *LINEAGE_DEPTH,
+                                    &*LOG_DIRECTORY,
+                                ) {
+                                    println!("[LOGGER] Failed to export lineage: {:?}", e);
+                                }
+                            }
                         };
-                        sum_fit += fit;
-                        let gen = creature.generation();
-                        if gen > max_gen {
-                            max_gen = gen
-                        };
-                        sum_gen += gen as f32;
-                        let len = creature.genome.len();
-                        sum_len += len;
                     }
                 }
             }
-            let mean_fitness = sum_fit / count as f32;
-            let mean_gen = sum_gen / count as f32;
-            let mean_len = sum_len as f32 / count as f32;
-            log(
-                &[
-                    ("MAX-GEN", max_gen as f32),
-                    ("MEAN-GEN", mean_gen),
-                    ("MEAN-FIT", mean_fitness),
-                    ("MAX-FIT", max_fitness),
-                    ("MEAN-LEN", mean_len),
-                ],
-                log_counter,
-            );
-            //      println!("[LOGGER] max gen: {}, mean gen: {:4.4}, mean fitness: {:1.5}, max fitness: {}, mean length: {}", max_gen, mean_gen, mean_fitness, max_fit, mean_len);
-            //sleep(Duration::from_millis(1000));
+            let stats = analyser.analyse(&window);
+            monitor::publish_stats(&stats, objective_maxima(&window));
+            log(&stats, &mut header);
+            analyses += 1;
+            if coverage_mode != CoverageMode::Off && coverage_due(analyses, *COVERAGE_PERIOD) {
+                let res = match coverage_mode {
+                    CoverageMode::Window => Coverage::from_window(&window)
+                        .write_reports(&*LOG_DIRECTORY, *COVERAGE_LINES),
+                    _ => coverage
+                        .read()
+                        .unwrap()
+                        .write_reports(&*LOG_DIRECTORY, *COVERAGE_LINES),
+                };
+                if let Err(e) = res {
+                    println!("[LOGGER] Failed to write coverage report: {:?}", e);
+                }
+            };
         }
     });
 
@@ -121,9 +169,24 @@ pub fn spawn_logger(
     let handle = spawn(move || {
         let mut count: u64 = 0;
         for incoming in log_rx {
+            counters::bump(&counters::LOGGED);
+            if *LINEAGE_DEPTH > 0 {
+                genealogy.write().unwrap().record(&incoming);
+            };
+            if coverage_mode == CoverageMode::Run {
+                run_coverage.write().unwrap().add_creature(&incoming);
+            };
             let mut received = received.write().unwrap();
             received.push(incoming);
             if count % analysis_period == 0 {
+                if *LINEAGE_DEPTH > 0 {
+                    let living = received
+                        .buf
+                        .iter()
+                        .map(|c| c.name.as_str())
+                        .collect::<Vec<&str>>();
+                    genealogy.write().unwrap().prune(&living, *LINEAGE_DEPTH);
+                };
                 analyse_tx.send(true).unwrap();
             };
             count += 1;
@@ -133,3 +196,12 @@ pub fn spawn_logger(
 
     (log_tx, handle)
 }
+
+#[test]
+fn test_coverage_due() {
+    assert!(!coverage_due(1, 0));
+    assert!(!coverage_due(16, 0));
+    assert!(coverage_due(1, 1));
+    assert!(!coverage_due(15, 16));
+    assert!(coverage_due(32, 16));
+}
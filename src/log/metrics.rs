@@ -0,0 +1,281 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+
+use crate::gen::Creature;
+use crate::monitor::MONITOR;
+use crate::par::counters;
+
+/* Every counter the pipeline keeps, under the label it's exported with. */
+fn stage_counters() -> [(&'static str, &'static counters::Counter); 9] {
+    [
+        ("seeded", &counters::SEEDED),
+        ("dispatched", &counters::DISPATCHED),
+        ("hatched", &counters::HATCHED),
+        ("already_hatched", &counters::ALREADY_HATCHED),
+        ("emulated", &counters::EMULATED),
+        ("evaluated", &counters::EVALUATED),
+        ("bred", &counters::BRED),
+        ("logged", &counters::LOGGED),
+        ("tournaments", &counters::TOURNAMENTS),
+    ]
+}
+
+/// Escapes a string for inclusion in a JSON document.
+pub fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn json_number(x: f32) -> String {
+    if x.is_finite() {
+        format!("{}", x)
+    } else {
+        "null".to_string()
+    }
+}
+
+/* Prometheus spells the non-finite values its own way. */
+fn prometheus_number(x: f32) -> String {
+    if x.is_nan() {
+        "NaN".to_string()
+    } else if x.is_infinite() {
+        if x > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        format!("{}", x)
+    }
+}
+
+/* Prometheus metric names can't contain dashes, and are conventionally
+ * lower case. */
+fn prometheus_name(stat: &str) -> String {
+    stat.to_lowercase().replace('-', "_")
+}
+
+pub fn prometheus_text() -> String {
+    let monitor = MONITOR.read().unwrap();
+    let mut text = String::new();
+    text.push_str("# HELP roper_uptime_seconds Seconds since the run began.\n");
+    text.push_str("# TYPE roper_uptime_seconds gauge\n");
+    text.push_str(&format!(
+        "roper_uptime_seconds {}\n",
+        monitor.started.elapsed().as_secs_f32()
+    ));
+
+    text.push_str("# HELP roper_creatures_total Creatures passed along by each pipeline stage.\n");
+    text.push_str("# TYPE roper_creatures_total counter\n");
+    for (stage, counter) in stage_counters().iter() {
+        text.push_str(&format!(
+            "roper_creatures_total{{stage=\"{}\"}} {}\n",
+            stage,
+            counters::read(counter)
+        ));
+    }
+
+    if let Some(latest) = monitor.latest() {
+        let mut last_metric = String::new();
+        for (name, value) in latest.iter() {
+            /* per-objective columns are named like OBJ-MEAN-0 */
+            let (stat, objective) = match name.rfind('-') {
+                Some(i) if name[i + 1..].parse::<usize>().is_ok() => {
+                    (&name[..i], Some(&name[i + 1..]))
+                }
+                _ => (name.as_str(), None),
+            };
+            let metric = format!("roper_{}", prometheus_name(stat));
+            if metric != last_metric {
+                text.push_str(&format!("# TYPE {} gauge\n", metric));
+                last_metric = metric.clone();
+            };
+            match objective {
+                Some(obj) => text.push_str(&format!(
+                    "{}{{objective=\"{}\"}} {}\n",
+                    metric,
+                    obj,
+                    prometheus_number(*value)
+                )),
+                None => text.push_str(&format!("{} {}\n", metric, prometheus_number(*value))),
+            }
+        }
+    }
+
+    if let Some(ref champion) = monitor.champion {
+        text.push_str("# HELP roper_champion_fitness Fitness of the best creature so far.\n");
+        text.push_str("# TYPE roper_champion_fitness gauge\n");
+        for (i, x) in champion.fitness.iter().flatten().enumerate() {
+            text.push_str(&format!(
+                "roper_champion_fitness{{objective=\"{}\"}} {}\n",
+                i,
+                prometheus_number(*x)
+            ));
+        }
+        text.push_str("# HELP roper_champion_generation Generation of the best creature so far.\n");
+        text.push_str("# TYPE roper_champion_generation gauge\n");
+        text.push_str(&format!(
+            "roper_champion_generation {}\n",
+            champion.generation()
+        ));
+    }
+    text
+}
+
+pub fn stats_json() -> String {
+    let monitor = MONITOR.read().unwrap();
+    let uptime = monitor.started.elapsed().as_secs_f32();
+    let stats = monitor
+        .latest()
+        .map(|latest| {
+            latest
+                .iter()
+                .map(|(name, value)| format!("{}: {}", json_string(name), json_number(*value)))
+                .collect::<Vec<String>>()
+                .join(", ")
+        })
+        .unwrap_or_default();
+    let stages = stage_counters()
+        .iter()
+        .map(|(stage, counter)| {
+            let total = counters::read(counter);
+            format!(
+                "{}: {{\"total\": {}, \"per_second\": {}}}",
+                json_string(stage),
+                total,
+                json_number(total as f32 / uptime)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    format!(
+        "{{\"uptime_seconds\": {}, \"statistics\": {{{}}}, \"stages\": {{{}}}}}\n",
+        json_number(uptime),
+        stats,
+        stages
+    )
+}
+
+pub fn creature_json(creature: &Creature) -> String {
+    let fitness = creature
+        .fitness
+        .iter()
+        .flatten()
+        .map(|x| json_number(*x))
+        .collect::<Vec<String>>()
+        .join(", ");
+    let genome = creature
+        .genome
+        .alleles
+        .iter()
+        .map(|a| json_string(&format!("{}", a)))
+        .collect::<Vec<String>>()
+        .join(", ");
+    let phenome = creature
+        .phenome
+        .iter()
+        .filter_map(|(input, pod)| pod.as_ref().map(|pod| (input, pod)))
+        .map(|(input, pod)| {
+            format!(
+                "{{\"input\": {:?}, \"visited\": [{}], \"retlog\": {:?}}}",
+                input,
+                pod.disas_visited()
+                    .iter()
+                    .map(|line| json_string(line))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                pod.retlog
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    format!(
+        "{{\"name\": {}, \"generation\": {}, \"pedigree\": {}, \"fitness\": [{}], \
+         \"genome\": [{}], \"phenome\": [{}]}}\n",
+        json_string(&creature.name),
+        creature.generation(),
+        json_string(&format!("{}", creature.pedigree)),
+        fitness,
+        genome,
+        phenome
+    )
+}
+
+/* How long a client gets to send its request line, or to take the
+ * response, before it's dropped for the next one. */
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let (status, content_type, body) = match path {
+        "/metrics" => ("200 OK", "text/plain; version=0.0.4", prometheus_text()),
+        "/stats" | "/stats.json" => ("200 OK", "application/json", stats_json()),
+        "/champion" | "/champion.json" => match MONITOR.read().unwrap().champion {
+            Some(ref champion) => ("200 OK", "application/json", creature_json(champion)),
+            None => ("404 Not Found", "application/json", "null\n".to_string()),
+        },
+        _ => (
+            "404 Not Found",
+            "text/plain",
+            "try /metrics, /stats or /champion\n".to_string(),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+/// Serves the logger's statistics, the pipeline counters and the
+/// current champion over HTTP, one request at a time, dropping any
+/// client that stalls for longer than a few seconds.
+pub fn spawn_metrics_server(address: &str) -> JoinHandle<()> {
+    let listener = TcpListener::bind(address)
+        .unwrap_or_else(|e| panic!("Failed to bind metrics listener to {}: {:?}", address, e));
+    println!("[+] Serving metrics on http://{}/metrics", address);
+    spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = respond(stream) {
+                        println!("[metrics] Error responding to request: {:?}", e);
+                    }
+                }
+                Err(e) => println!("[metrics] Failed connection: {:?}", e),
+            }
+        }
+    })
+}
+
+#[test]
+fn test_json_string() {
+    assert_eq!(json_string("plain"), "\"plain\"");
+    assert_eq!(json_string("a \"b\"\n"), "\"a \\\"b\\\"\\n\"");
+    assert_eq!(json_string("\u{1}"), "\"\\u0001\"");
+}
+
+#[test]
+fn test_prometheus_number() {
+    assert_eq!(prometheus_number(0.5), "0.5");
+    assert_eq!(prometheus_number(std::f32::INFINITY), "+Inf");
+    assert_eq!(prometheus_number(std::f32::NEG_INFINITY), "-Inf");
+    assert_eq!(prometheus_number(std::f32::NAN), "NaN");
+}
@@ -3,3 +3,21 @@ pub use crate::disas::*;
 
 pub mod logger;
 pub use crate::logger::*;
+
+pub mod lineage;
+pub use crate::lineage::*;
+
+pub mod stats;
+pub use crate::stats::*;
+
+pub mod monitor;
+pub use crate::monitor::*;
+
+pub mod dashboard;
+pub use crate::dashboard::*;
+
+pub mod metrics;
+pub use crate::metrics::*;
+
+pub mod coverage;
+pub use crate::coverage::*;
@@ -0,0 +1,78 @@
+use std::collections::VecDeque;
+use std::sync::RwLock;
+use std::time::Instant;
+
+use crate::gen::Creature;
+
+/// How many analyses' worth of statistics to keep for plotting.
+pub const HISTORY_LENGTH: usize = 512;
+
+/* The logger publishes its findings here, so that the dashboard (and
+ * anything else that wants to watch a run from outside the pipeline)
+ * can read them without having to sit on a channel.
+ */
+pub struct Monitor {
+    pub started: Instant,
+    pub history: VecDeque<Vec<(String, f32)>>,
+    pub objectives: VecDeque<Vec<f32>>,
+    pub champion: Option<Creature>,
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Monitor {
+            started: Instant::now(),
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+            objectives: VecDeque::with_capacity(HISTORY_LENGTH),
+            champion: None,
+        }
+    }
+
+    pub fn latest(&self) -> Option<&Vec<(String, f32)>> {
+        self.history.back()
+    }
+
+    /// The history of a single statistic, oldest first.
+    pub fn series(&self, name: &str) -> Vec<f32> {
+        self.history
+            .iter()
+            .filter_map(|row| row.iter().find(|(n, _)| n == name).map(|(_, x)| *x))
+            .collect::<Vec<f32>>()
+    }
+
+    /// The history of the best score on objective `i`, oldest first.
+    pub fn objective_series(&self, i: usize) -> Vec<f32> {
+        self.objectives
+            .iter()
+            .filter_map(|row| row.get(i).copied())
+            .collect::<Vec<f32>>()
+    }
+
+    pub fn num_objectives(&self) -> usize {
+        self.objectives.back().map(|row| row.len()).unwrap_or(0)
+    }
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Monitor::new()
+    }
+}
+
+lazy_static! {
+    pub static ref MONITOR: RwLock<Monitor> = RwLock::new(Monitor::new());
+}
+
+pub fn publish_stats(stats: &[(String, f32)], objectives: Vec<f32>) {
+    let mut monitor = MONITOR.write().unwrap();
+    if monitor.history.len() == HISTORY_LENGTH {
+        monitor.history.pop_front();
+        monitor.objectives.pop_front();
+    };
+    monitor.history.push_back(stats.to_vec());
+    monitor.objectives.push_back(objectives);
+}
+
+pub fn publish_champion(champion: &Creature) {
+    MONITOR.write().unwrap().champion = Some(champion.clone());
+}
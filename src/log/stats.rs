@@ -0,0 +1,272 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::time::Instant;
+
+use crate::fit::CircBuf;
+use crate::gen::{Creature, FitnessOps};
+use crate::par::counters;
+
+/// The statistics the logger knows how to compute over its window.
+/// Each is selected by name in the `statistics` field of `[Logging]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stat {
+    MaxGen,
+    MeanGen,
+    MeanFit,
+    MaxFit,
+    MeanLen,
+    /* per-objective statistics, one column for each element of the
+     * fitness vector */
+    ObjMin,
+    ObjMean,
+    ObjMax,
+    ObjStdDev,
+    /* genotypic diversity */
+    UniqGenomes,
+    XbitsDistance,
+    /* phenotypic diversity */
+    UniqBehaviours,
+    CrashRate,
+    HatchRate,
+}
+
+impl Stat {
+    pub fn from_name(name: &str) -> Option<Stat> {
+        match name.to_lowercase().as_str() {
+            "max-gen" => Some(Stat::MaxGen),
+            "mean-gen" => Some(Stat::MeanGen),
+            "mean-fit" => Some(Stat::MeanFit),
+            "max-fit" => Some(Stat::MaxFit),
+            "mean-len" => Some(Stat::MeanLen),
+            "obj-min" => Some(Stat::ObjMin),
+            "obj-mean" => Some(Stat::ObjMean),
+            "obj-max" => Some(Stat::ObjMax),
+            "obj-stddev" => Some(Stat::ObjStdDev),
+            "uniq-genomes" => Some(Stat::UniqGenomes),
+            "xbits-distance" => Some(Stat::XbitsDistance),
+            "uniq-behaviours" => Some(Stat::UniqBehaviours),
+            "crash-rate" => Some(Stat::CrashRate),
+            "hatch-rate" => Some(Stat::HatchRate),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stat::MaxGen => "MAX-GEN",
+            Stat::MeanGen => "MEAN-GEN",
+            Stat::MeanFit => "MEAN-FIT",
+            Stat::MaxFit => "MAX-FIT",
+            Stat::MeanLen => "MEAN-LEN",
+            Stat::ObjMin => "OBJ-MIN",
+            Stat::ObjMean => "OBJ-MEAN",
+            Stat::ObjMax => "OBJ-MAX",
+            Stat::ObjStdDev => "OBJ-STDDEV",
+            Stat::UniqGenomes => "UNIQ-GENOMES",
+            Stat::XbitsDistance => "XBITS-DISTANCE",
+            Stat::UniqBehaviours => "UNIQ-BEHAVIOURS",
+            Stat::CrashRate => "CRASH-RATE",
+            Stat::HatchRate => "HATCH-RATE",
+        }
+    }
+}
+
+/// Parses a whitespace or comma separated list of statistic names.
+pub fn parse_stats(spec: &str) -> Vec<Stat> {
+    spec.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| Stat::from_name(s).unwrap_or_else(|| panic!("Unknown statistic: {}", s)))
+        .collect::<Vec<Stat>>()
+}
+
+/// Computes the selected statistics over the logger's sliding window.
+/// Running maxima and throughput are carried from one call to the next.
+pub struct Analyser {
+    pub selected: Vec<Stat>,
+    max_fitness: f32,
+    max_gen: usize,
+    last_hatched: usize,
+    last_instant: Instant,
+}
+
+impl Analyser {
+    pub fn new(selected: Vec<Stat>) -> Self {
+        Analyser {
+            selected,
+            max_fitness: 0.0,
+            max_gen: 0,
+            last_hatched: counters::read(&counters::HATCHED),
+            last_instant: Instant::now(),
+        }
+    }
+
+    pub fn analyse(&mut self, window: &CircBuf) -> Vec<(String, f32)> {
+        let creatures = window
+            .buf
+            .iter()
+            .filter(|c| c.fitness.is_some())
+            .collect::<Vec<&Creature>>();
+        let count = creatures.len() as f32;
+        let num_objectives = creatures
+            .iter()
+            .map(|c| c.fitness.as_ref().unwrap().len())
+            .max()
+            .unwrap_or(0);
+
+        for creature in creatures.iter() {
+            let fit = creature.fitness.as_ref().unwrap().mean();
+            if fit > self.max_fitness {
+                self.max_fitness = fit
+            };
+            let gen = creature.generation();
+            if gen > self.max_gen {
+                self.max_gen = gen
+            };
+        }
+
+        let mut stats = Vec::new();
+        for stat in self.selected.clone() {
+            match stat {
+                Stat::MaxGen => stats.push((stat.name().to_string(), self.max_gen as f32)),
+                Stat::MaxFit => stats.push((stat.name().to_string(), self.max_fitness)),
+                Stat::MeanGen => stats.push((
+                    stat.name().to_string(),
+                    mean(creatures.iter().map(|c| c.generation() as f32)),
+                )),
+                Stat::MeanFit => stats.push((
+                    stat.name().to_string(),
+                    mean(creatures.iter().map(|c| c.fitness.as_ref().unwrap().mean())),
+                )),
+                Stat::MeanLen => stats.push((
+                    stat.name().to_string(),
+                    mean(creatures.iter().map(|c| c.genome.len() as f32)),
+                )),
+                Stat::ObjMin | Stat::ObjMean | Stat::ObjMax | Stat::ObjStdDev => {
+                    for i in 0..num_objectives {
+                        let scores = creatures
+                            .iter()
+                            .filter_map(|c| c.fitness.as_ref().unwrap().get(i).copied())
+                            .collect::<Vec<f32>>();
+                        let value = match stat {
+                            Stat::ObjMin => scores.iter().cloned().fold(std::f32::NAN, f32::min),
+                            Stat::ObjMax => scores.iter().cloned().fold(std::f32::NAN, f32::max),
+                            Stat::ObjMean => mean(scores.iter().cloned()),
+                            _ => stddev(&scores),
+                        };
+                        stats.push((format!("{}-{}", stat.name(), i), value));
+                    }
+                }
+                Stat::UniqGenomes => {
+                    let names = creatures
+                        .iter()
+                        .map(|c| &c.name)
+                        .collect::<HashSet<&String>>();
+                    stats.push((stat.name().to_string(), names.len() as f32 / count))
+                }
+                Stat::XbitsDistance => stats.push((
+                    stat.name().to_string(),
+                    mean_hamming_distance(
+                        &creatures
+                            .iter()
+                            .map(|c| c.genome.xbits)
+                            .collect::<Vec<u64>>(),
+                    ),
+                )),
+                Stat::UniqBehaviours => {
+                    let signatures = creatures
+                        .iter()
+                        .map(|c| behaviour_signature(c))
+                        .collect::<HashSet<u64>>();
+                    stats.push((stat.name().to_string(), signatures.len() as f32 / count))
+                }
+                Stat::CrashRate => {
+                    let (crashed, total) = creatures
+                        .iter()
+                        .flat_map(|c| c.phenome.values())
+                        .filter_map(|p| p.as_ref())
+                        .fold((0, 0), |(crashed, total), pod| {
+                            (crashed + pod.crashed() as usize, total + 1)
+                        });
+                    let rate = if total == 0 {
+                        0.0
+                    } else {
+                        crashed as f32 / total as f32
+                    };
+                    stats.push((stat.name().to_string(), rate))
+                }
+                Stat::HatchRate => {
+                    let hatched = counters::read(&counters::HATCHED);
+                    let elapsed = self.last_instant.elapsed().as_secs_f32();
+                    let rate = (hatched - self.last_hatched) as f32 / elapsed;
+                    self.last_hatched = hatched;
+                    self.last_instant = Instant::now();
+                    stats.push((stat.name().to_string(), rate))
+                }
+            }
+        }
+        stats
+    }
+}
+
+/// The best score on each objective, over the whole window.
+pub fn objective_maxima(window: &CircBuf) -> Vec<f32> {
+    let mut maxima: Vec<f32> = Vec::new();
+    for fvec in window.buf.iter().filter_map(|c| c.fitness.as_ref()) {
+        for (i, x) in fvec.iter().enumerate() {
+            if i >= maxima.len() {
+                maxima.push(*x)
+            } else if *x > maxima[i] {
+                maxima[i] = *x
+            };
+        }
+    }
+    maxima
+}
+
+fn mean<I: Iterator<Item = f32>>(xs: I) -> f32 {
+    let (sum, n) = xs.fold((0.0, 0), |(sum, n), x| (sum + x, n + 1));
+    sum / n as f32
+}
+
+fn stddev(xs: &[f32]) -> f32 {
+    let mu = mean(xs.iter().cloned());
+    mean(xs.iter().map(|x| (x - mu) * (x - mu))).sqrt()
+}
+
+/// Mean Hamming distance over all pairs, counted bitwise so that it
+/// stays linear in the size of the window.
+fn mean_hamming_distance(xbits: &[u64]) -> f32 {
+    let n = xbits.len();
+    if n < 2 {
+        return 0.0;
+    };
+    let mut differing_pairs = 0;
+    for bit in 0..64 {
+        let ones = xbits.iter().filter(|&&x| x & (1 << bit) != 0).count();
+        differing_pairs += ones * (n - ones);
+    }
+    differing_pairs as f32 / (n * (n - 1) / 2) as f32
+}
+
+/// Hashes the return logs of every hatched Pod, in input order, so that
+/// creatures that behave identically share a signature.
+fn behaviour_signature(creature: &Creature) -> u64 {
+    let mut inputs = creature.phenome.keys().collect::<Vec<_>>();
+    inputs.sort();
+    let mut hasher = DefaultHasher::new();
+    for input in inputs {
+        if let Some(Some(pod)) = creature.phenome.get(input) {
+            pod.retlog.hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+#[test]
+fn test_mean_hamming_distance() {
+    assert_eq!(mean_hamming_distance(&[0b1010]), 0.0);
+    assert_eq!(mean_hamming_distance(&[0b1010, 0b0101]), 4.0);
+    /* pairs: (0,1) -> 1, (0,3) -> 2, (1,3) -> 1 */
+    assert_eq!(mean_hamming_distance(&[0b00, 0b01, 0b11]), 4.0 / 3.0);
+}
@@ -0,0 +1,36 @@
+/* Process-wide event counters, bumped by the pipeline stages and read
+ * by the logger. Relaxed ordering is fine: these are only ever used
+ * for statistics, never for synchronisation.
+ */
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub type Counter = AtomicUsize;
+
+/// Creatures generated from scratch by the seeder.
+pub static SEEDED: Counter = AtomicUsize::new(0);
+/// Creatures the hatchery has sent on to an emulator in the carousel.
+pub static DISPATCHED: Counter = AtomicUsize::new(0);
+/// Creatures that have been run through an emulator, once for each case.
+pub static HATCHED: Counter = AtomicUsize::new(0);
+/// Creatures the hatchery passed over, since they'd already hatched.
+pub static ALREADY_HATCHED: Counter = AtomicUsize::new(0);
+/// Creatures that have come back out of an emulator in the carousel.
+pub static EMULATED: Counter = AtomicUsize::new(0);
+/// Creatures assigned a fitness by the evaluator.
+pub static EVALUATED: Counter = AtomicUsize::new(0);
+/// Tournaments held by the breeder.
+pub static TOURNAMENTS: Counter = AtomicUsize::new(0);
+/// Offspring sent back to the hatchery by the breeder.
+pub static BRED: Counter = AtomicUsize::new(0);
+/// Creatures received by the logger.
+pub static LOGGED: Counter = AtomicUsize::new(0);
+
+#[inline]
+pub fn bump(counter: &Counter) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn read(counter: &Counter) -> usize {
+    counter.load(Ordering::Relaxed)
+}
@@ -1,2 +1,4 @@
 pub mod statics;
 pub use self::statics::*;
+
+pub mod counters;
@@ -11,11 +11,15 @@ use std::path::Path;
 use std::sync::{Arc, RwLock};
 
 use self::goblin::elf::header::machine_to_str;
+use self::goblin::mach::cputype::{CPU_TYPE_ARM, CPU_TYPE_ARM64, CPU_TYPE_X86, CPU_TYPE_X86_64};
+use self::goblin::pe::header::{
+    COFF_MACHINE_ARM, COFF_MACHINE_ARM64, COFF_MACHINE_ARMNT, COFF_MACHINE_X86, COFF_MACHINE_X86_64,
+};
 use self::goblin::Object;
-use self::ini::Ini;
+use self::ini::{Ini, Properties};
 use self::num::PrimInt;
 
-use crate::emu::loader::{Arch, Mode};
+use crate::emu::loader::{select_macho, Arch, Mode};
 lazy_static! {
     pub static ref ROPER_INI_PATH: String = match env::var("ROPER_INI_PATH") {
         Err(_) => ".roper_config/roper.ini".to_string(),
@@ -46,61 +50,238 @@ lazy_static! {
         };
 }
 lazy_static! {
-    pub static ref CODE_BUFFER: Vec<u8>
-        = {
-            /* first, read the config */
-            let bp = match env::var("ROPER_BINARY") {
-                Ok(s)  => s,
-                Err(_) => {
-                    INI.section(Some("Binary"))
-                        .expect("Couldn't find Binary section in INI")
-                        .get("path")
-                        .expect("Couldn't find path field in Binary section of INI")
-                        .to_string()
+    pub static ref BINARY_PATH: String = match env::var("ROPER_BINARY") {
+        Ok(s) => s,
+        Err(_) => INI
+            .section(Some("Binary"))
+            .expect("Couldn't find Binary section in INI")
+            .get("path")
+            .expect("Couldn't find path field in Binary section of INI")
+            .to_string(),
+    };
+}
+
+pub fn read_file(path: &str) -> Vec<u8> {
+    //println!("[*] Read binary path as {:?}",path);
+    let mut fd =
+        File::open(Path::new(path)).unwrap_or_else(|_| panic!("Can't read binary at {:?}", path));
+    let mut buffer = Vec::new();
+    fd.read_to_end(&mut buffer).unwrap();
+    buffer
+}
+
+lazy_static! {
+    pub static ref CODE_BUFFER: Vec<u8> = read_file(&BINARY_PATH);
+}
+
+/// A shared library to be mapped alongside the binary.
+pub struct Library {
+    pub path: String,
+    pub base: u64,
+    pub weight: f32,
+    pub buffer: Vec<u8>,
+}
+
+/// Parses a library given as `path@base`, or `path@base*weight`.
+pub fn parse_library(spec: &str) -> (String, u64, f32) {
+    let (spec, weight) = match spec.rfind('*') {
+        Some(i) => (
+            &spec[..i],
+            spec[i + 1..]
+                .parse::<f32>()
+                .unwrap_or_else(|_| panic!("Bad weight in library {:?}", spec)),
+        ),
+        None => (spec, 1.0),
+    };
+    let i = spec
+        .rfind('@')
+        .unwrap_or_else(|| panic!("Library {:?} needs a load address, as path@base", spec));
+    let base = parse_number(&spec[i + 1..]);
+    if base & 0xFFF != 0 {
+        panic!("Library {:?} must be loaded at a page boundary", spec);
+    };
+    (spec[..i].to_string(), base, weight)
+}
+
+lazy_static! {
+    /* comma or space separated, each one path@base[*weight] */
+    pub static ref LIBRARIES: Vec<Library> =
+        lookup_string_setting("Binary", "libraries", "".to_string())
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|l| !l.is_empty())
+            .map(|l| {
+                let (path, base, weight) = parse_library(l);
+                let buffer = read_file(&path);
+                Library {
+                    path,
+                    base,
+                    weight,
+                    buffer,
                 }
-            };
-            //println!("[*] Read binary path as {:?}",bp);
-            let path = Path::new(&bp);
-            let mut fd = File::open(path).unwrap_or_else(|_| panic!("Can't read binary at {:?}",bp));
-            let mut buffer = Vec::new();
-            fd.read_to_end(&mut buffer).unwrap();
-            buffer
-        };
+            })
+            .collect();
 }
 
-// set addr size here too. dispense with risc_width() calls, which are confused
 lazy_static! {
-    pub static ref ARCHITECTURE: Arch = {
-        let arch_magic = match Object::parse(&CODE_BUFFER).unwrap() {
-            Object::Elf(e) => machine_to_str(e.header.e_machine),
-            _ => panic!("Binary format unimplemented."),
-        };
-        match arch_magic {
-            "ARM" => Arch::Arm(Mode::Arm),
-            "MIPS" => Arch::Mips(Mode::Be),
-            "MIPS_RS3_LE" => Arch::Mips(Mode::Le),
-            "X86_64" => Arch::X86(Mode::Bits64),
-            "386" => Arch::X86(Mode::Bits32),
-            _ => panic!("arch_magic not recognized!"),
-        }
+    /* the weight of the binary itself, against those of the libraries */
+    pub static ref BINARY_WEIGHT: f32 = lookup_f32_setting("Binary", "weight", 1.0);
+}
+
+/// Works out the architecture, and its default mode, from the header
+/// of an executable.
+pub fn detect_arch(buffer: &[u8]) -> Arch {
+    let (arch_magic, little_endian, is_64) = match Object::parse(buffer).unwrap() {
+        Object::Elf(e) => (machine_to_str(e.header.e_machine), e.little_endian, e.is_64),
+        Object::PE(pe) => return pe_arch(pe.header.coff_header.machine),
+        Object::Mach(mach) => return macho_arch(select_macho(mach).header.cputype),
+        _ => panic!("Binary format unimplemented."),
+    };
+    let endian = if little_endian { Mode::Le } else { Mode::Be };
+    match arch_magic {
+        "ARM" => Arch::Arm(Mode::Arm),
+        "AARCH64" => Arch::Arm64(endian),
+        "MIPS" => Arch::Mips(endian),
+        "MIPS_RS3_LE" => Arch::Mips(Mode::Le),
+        "X86_64" => Arch::X86(Mode::Bits64),
+        "386" => Arch::X86(Mode::Bits32),
+        "RISCV" => Arch::RiscV(if is_64 { Mode::Bits64 } else { Mode::Bits32 }),
+        "PPC" => Arch::Ppc(endian),
+        "PPC64" => Arch::Ppc64(endian),
+        _ => panic!("arch_magic not recognized!"),
+    }
+}
+
+/* The machine field of a COFF header. Windows on ARM runs Thumb-2. */
+fn pe_arch(machine: u16) -> Arch {
+    match machine {
+        COFF_MACHINE_X86 => Arch::X86(Mode::Bits32),
+        COFF_MACHINE_X86_64 => Arch::X86(Mode::Bits64),
+        COFF_MACHINE_ARM => Arch::Arm(Mode::Arm),
+        COFF_MACHINE_ARMNT => Arch::Arm(Mode::Thumb),
+        COFF_MACHINE_ARM64 => Arch::Arm64(Mode::Le),
+        _ => panic!("COFF machine type 0x{:x} not recognized!", machine),
+    }
+}
+
+/// The architecture of a raw binary, named in the config, since there's
+/// no header to read it from.
+pub fn raw_arch(name: &str, endian: &str) -> Arch {
+    let endian = match endian.to_lowercase().as_str() {
+        "little" | "le" => Mode::Le,
+        "big" | "be" => Mode::Be,
+        _ => panic!("Unknown endianness: {}", endian),
     };
+    match name.to_lowercase().as_str() {
+        "x86_64" | "amd64" => Arch::X86(Mode::Bits64),
+        "x86" | "i386" => Arch::X86(Mode::Bits32),
+        "arm" => Arch::Arm(Mode::Arm),
+        "thumb" => Arch::Arm(Mode::Thumb),
+        "arm64" | "aarch64" => Arch::Arm64(endian),
+        "mips" => Arch::Mips(endian),
+        "riscv32" => Arch::RiscV(Mode::Bits32),
+        "riscv64" => Arch::RiscV(Mode::Bits64),
+        "ppc" => Arch::Ppc(endian),
+        "ppc64" => Arch::Ppc64(endian),
+        _ => panic!("Unknown architecture: {}", name),
+    }
+}
+
+/// Reads a number from the config, in hex if it starts with 0x.
+pub fn parse_number(s: &str) -> u64 {
+    let s = s.trim();
+    let n = if s.starts_with("0x") || s.starts_with("0X") {
+        u64::from_str_radix(&s[2..], 16)
+    } else {
+        s.parse::<u64>()
+    };
+    n.unwrap_or_else(|_| panic!("Failed to parse number: {:?}", s))
+}
+
+fn macho_arch(cputype: u32) -> Arch {
+    match cputype {
+        CPU_TYPE_X86_64 => Arch::X86(Mode::Bits64),
+        CPU_TYPE_X86 => Arch::X86(Mode::Bits32),
+        CPU_TYPE_ARM64 => Arch::Arm64(Mode::Le),
+        CPU_TYPE_ARM => Arch::Arm(Mode::Arm),
+        _ => panic!("Mach-O cpu type 0x{:x} not recognized!", cputype),
+    }
+}
+
+/// The width, in bytes, of an address, and so of each word of a chain.
+pub fn addr_width(arch: Arch) -> usize {
+    match arch {
+        Arch::X86(Mode::Bits64) => 8,
+        Arch::X86(_) => 4,
+        Arch::Arm64(_) => 8,
+        Arch::RiscV(Mode::Bits64) => 8,
+        Arch::Ppc64(_) => 8,
+        _ => 4,
+    }
 }
 
 lazy_static! {
-    pub static ref ADDR_WIDTH: usize = {
-        match *ARCHITECTURE {
-            Arch::X86(_) => 8,
-            _ => 4,
-        }
+    /* an ELF core file, or a directory holding a maps file, the regions
+     * it lists and a registers file, to start emulation from instead of
+     * the freshly loaded binary; empty for none */
+    pub static ref SNAPSHOT_PATH: String =
+        lookup_string_setting("Binary", "snapshot", "".to_string());
+}
+
+lazy_static! {
+    /* raw, for a headerless image such as a flash dump, to be loaded
+     * according to the arch, endian, base and regions settings below;
+     * anything else means the format is read from the file itself */
+    pub static ref RAW_BINARY: bool =
+        lookup_string_setting("Binary", "format", "auto".to_string()).to_lowercase() == "raw";
+}
+
+lazy_static! {
+    pub static ref RAW_ARCH: String = lookup_string_setting("Binary", "arch", "arm".to_string());
+}
+
+lazy_static! {
+    pub static ref RAW_ENDIAN: String =
+        lookup_string_setting("Binary", "endian", "little".to_string());
+}
+
+lazy_static! {
+    pub static ref RAW_BASE: u64 =
+        parse_number(&lookup_string_setting("Binary", "base", "0".to_string()));
+}
+
+lazy_static! {
+    /* the memory map, see loader::parse_regions */
+    pub static ref RAW_REGIONS: String =
+        lookup_string_setting("Binary", "regions", "".to_string());
+}
+
+lazy_static! {
+    /* which slice of a fat Mach-O binary to load, by architecture name */
+    pub static ref FAT_ARCH: String =
+        lookup_string_setting("Binary", "fat_arch", "x86_64".to_string());
+}
+
+lazy_static! {
+    pub static ref ARCHITECTURE: Arch = if *RAW_BINARY {
+        raw_arch(&RAW_ARCH, &RAW_ENDIAN)
+    } else {
+        detect_arch(&CODE_BUFFER)
     };
 }
 
+lazy_static! {
+    pub static ref ADDR_WIDTH: usize = addr_width(*ARCHITECTURE);
+}
+
 /// A tiny machine word formatter
 #[inline]
 pub fn wf<T: PrimInt + fmt::LowerHex>(w: T) -> String {
     match *ARCHITECTURE {
-        Arch::X86(Mode::Bits64) => format!("{:016x}", w),
+        Arch::X86(Mode::Bits64) | Arch::Arm64(_) | Arch::RiscV(Mode::Bits64) | Arch::Ppc64(_) => {
+            format!("{:016x}", w)
+        }
         Arch::X86(Mode::Bits16) => format!("{:04x}", w),
+        Arch::X86(Mode::Bits32) => format!("{:08x}", w),
         _ => format!("{:08x}", w),
     }
 }
@@ -130,6 +311,12 @@ fn lookup_string_setting(section: &str, item: &str, default: String) -> String {
     }
 }
 
+fn lookup_bool_setting(section: &str, item: &str, default: bool) -> bool {
+    let default = format!("{}", default); /* KLUDGE */
+    let str_setting = lookup_string_setting(section, item, default);
+    (&str_setting).parse::<bool>().unwrap()
+}
+
 fn lookup_f32_setting(section: &str, item: &str, default: f32) -> f32 {
     let sec = INI.section(Some(section.to_owned()));
     let dstr = format!("{}", default); /* KLUDGE */
@@ -223,3 +410,436 @@ lazy_static! {
     pub static ref LOG_DIRECTORY: String =
         lookup_string_setting("Logging", "log_directory", "./logs".to_string());
 }
+
+lazy_static! {
+    /* how many generations back to trace a champion's lineage; 0 disables */
+    pub static ref LINEAGE_DEPTH: usize = lookup_usize_setting("Logging", "lineage_depth", 64);
+}
+
+lazy_static! {
+    /* names of the statistics the logger reports, see log::stats */
+    pub static ref STATISTICS: String = lookup_string_setting(
+        "Logging",
+        "statistics",
+        "max-gen mean-gen mean-fit max-fit mean-len".to_string()
+    );
+}
+
+lazy_static! {
+    /* if true, draw a live dashboard on the terminal instead of
+     * printing the statistics to stdout */
+    pub static ref DASHBOARD: bool = lookup_bool_setting("Logging", "dashboard", false);
+}
+
+lazy_static! {
+    pub static ref DASHBOARD_REFRESH_MS: usize =
+        lookup_usize_setting("Logging", "dashboard_refresh_ms", 1000);
+}
+
+lazy_static! {
+    /* off, window or run: whether to write a coverage report of the
+     * addresses executed, over the sliding window or the whole run */
+    pub static ref COVERAGE: String =
+        lookup_string_setting("Logging", "coverage", "off".to_string());
+}
+
+lazy_static! {
+    /* write the coverage report every this many analyses */
+    pub static ref COVERAGE_PERIOD: usize = lookup_usize_setting("Logging", "coverage_period", 16);
+}
+
+lazy_static! {
+    /* how many functions and addresses to list in the text report */
+    pub static ref COVERAGE_LINES: usize = lookup_usize_setting("Logging", "coverage_lines", 64);
+}
+
+lazy_static! {
+    pub static ref METRICS_ENABLED: bool = lookup_bool_setting("Metrics", "enabled", false);
+}
+
+lazy_static! {
+    pub static ref METRICS_ADDRESS: String =
+        lookup_string_setting("Metrics", "address", "127.0.0.1:9184".to_string());
+}
+
+/// The state to set up before hatching: registers, words on the stack
+/// on either side of the payload, and bytes written to memory, as they
+/// would be in the vulnerable function when the overflow returns.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EmulationSetup {
+    pub registers: Vec<(String, u64)>,
+    /* below the payload, at lower addresses, in address order */
+    pub stack_below: Vec<u64>,
+    /* after the payload, in address order */
+    pub stack_above: Vec<u64>,
+    pub preload: Vec<(u64, Vec<u8>)>,
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|x| !x.is_empty())
+}
+
+/* registers = rdi=0x601000, rsi=0 */
+fn parse_registers(list: &str) -> Vec<(String, u64)> {
+    split_list(list)
+        .map(|r| {
+            let eq = r
+                .find('=')
+                .unwrap_or_else(|| panic!("Bad register setting {:?}, expected name=value", r));
+            (r[..eq].to_lowercase(), parse_number(&r[eq + 1..]))
+        })
+        .collect()
+}
+
+/* preload = 0x601000:2f62696e2f736800 */
+fn parse_preload(list: &str) -> Vec<(u64, Vec<u8>)> {
+    split_list(list)
+        .map(|p| {
+            let colon = p
+                .find(':')
+                .unwrap_or_else(|| panic!("Bad preload {:?}, expected addr:hexbytes", p));
+            let hex = &p[colon + 1..];
+            if hex.len() % 2 != 0 {
+                panic!("Odd number of hex digits in preload {:?}", p);
+            };
+            let bytes = (0..hex.len())
+                .step_by(2)
+                .map(|i| {
+                    u8::from_str_radix(&hex[i..i + 2], 16)
+                        .unwrap_or_else(|_| panic!("Bad hex in preload {:?}", p))
+                })
+                .collect();
+            (parse_number(&p[..colon]), bytes)
+        })
+        .collect()
+}
+
+impl EmulationSetup {
+    /* Settings missing from the section are inherited from self, except
+     * for the registers, which are merged by name. */
+    fn overridden_by(&self, section: &Properties) -> Self {
+        let mut setup = self.clone();
+        if let Some(list) = section.get("registers") {
+            for (name, value) in parse_registers(list) {
+                setup.registers.retain(|(n, _)| *n != name);
+                setup.registers.push((name, value));
+            }
+        };
+        if let Some(list) = section.get("stack_below") {
+            setup.stack_below = split_list(list).map(parse_number).collect();
+        };
+        if let Some(list) = section.get("stack_above") {
+            setup.stack_above = split_list(list).map(parse_number).collect();
+        };
+        if let Some(list) = section.get("preload") {
+            setup.preload = parse_preload(list);
+        };
+        setup
+    }
+}
+
+lazy_static! {
+    pub static ref EMULATION: EmulationSetup = match INI.section(Some("Emulation")) {
+        Some(section) => EmulationSetup::default().overridden_by(section),
+        None => EmulationSetup::default(),
+    };
+}
+
+lazy_static! {
+    /* per-problem overrides, in sections named for the problem's input
+     * words, like [Emulation:1,2] */
+    static ref EMULATION_OVERRIDES: Vec<(Vec<u64>, EmulationSetup)> = {
+        let mut overrides = Vec::new();
+        for (name, section) in INI.iter() {
+            if let Some(name) = name {
+                if name.starts_with("Emulation:") {
+                    let input = split_list(&name["Emulation:".len()..])
+                        .map(parse_number)
+                        .collect::<Vec<u64>>();
+                    overrides.push((input, EMULATION.overridden_by(section)));
+                }
+            }
+        }
+        overrides
+    };
+}
+
+lazy_static! {
+    /* where to map the stack; by default, just above the rest of the image */
+    pub static ref STACK_BASE: Option<u64> = lookup_address_setting("Emulation", "stack_base");
+    /* with a snapshot, run chains on its own stack, from its saved sp,
+     * rather than on a fresh one */
+    pub static ref SNAPSHOT_STACK: bool = lookup_bool_setting("Emulation", "snapshot_stack", true);
+}
+
+lazy_static! {
+    pub static ref STACK_SIZE: usize = parse_number(&lookup_string_setting(
+        "Emulation",
+        "stack_size",
+        "0x1000".to_string()
+    )) as usize;
+}
+
+lazy_static! {
+    /* where the payload starts, in bytes relative to the initial sp, which
+     * is placed in the middle of the stack. By default, one word below,
+     * as if its first word had just been popped as a return address. */
+    pub static ref PAYLOAD_OFFSET: i64 =
+        match lookup_string_setting("Emulation", "payload_offset", "".to_string()).as_str() {
+            "" => -(*ADDR_WIDTH as i64),
+            offset => parse_signed(offset),
+        };
+}
+
+lazy_static! {
+    /* how much of its fitness a chain loses, in proportion to how much
+     * of its payload doesn't fit on the stack */
+    pub static ref OVERFLOW_PENALTY: f32 = lookup_f32_setting("Emulation", "overflow_penalty", 0.5);
+}
+
+lazy_static! {
+    /* how many instructions a chain may run, and for how long, in
+     * microseconds, or 0 for no time limit */
+    pub static ref INSTRUCTION_BUDGET: usize = lookup_usize_setting("Emulation", "instruction_budget", 1024);
+    pub static ref EMULATION_TIMEOUT: u64 = lookup_usize_setting("Emulation", "timeout_us", 0) as u64;
+}
+
+lazy_static! {
+    /* stop a chain that reaches this address, or returns to this one */
+    pub static ref TARGET_ADDRESS: Option<u64> = lookup_address_setting("Emulation", "target");
+    pub static ref SENTINEL_ADDRESS: Option<u64> = lookup_address_setting("Emulation", "sentinel");
+}
+
+lazy_static! {
+    /* whether to log reads, and from where; with no ranges, reads from
+     * anywhere are logged */
+    pub static ref LOG_READS: bool = lookup_bool_setting("Emulation", "log_reads", false);
+    pub static ref READ_RANGES: Vec<(u64, u64)> =
+        parse_ranges(&lookup_string_setting("Emulation", "read_ranges", "".to_string()));
+}
+
+/* read_ranges = 0x601000-0x602000, 0x7fff0000-0x7fff1000 */
+fn parse_ranges(list: &str) -> Vec<(u64, u64)> {
+    split_list(list)
+        .map(|r| {
+            let dash = r
+                .find('-')
+                .unwrap_or_else(|| panic!("Bad range {:?}, expected start-end", r));
+            (parse_number(&r[..dash]), parse_number(&r[dash + 1..]))
+        })
+        .collect()
+}
+
+lazy_static! {
+    /* check, after every restore, that writeable memory is back as it was
+     * saved. Slow, but it'll catch writes that the dirty pages miss. */
+    pub static ref VERIFY_RESTORE: bool = lookup_bool_setting("Emulation", "verify_restore", false);
+}
+
+lazy_static! {
+    /* how much of its fitness a chain loses, in proportion to how many of
+     * its cases crash on a fault it had no hand in */
+    pub static ref CRASH_PENALTY: f32 = lookup_f32_setting("Emulation", "crash_penalty", 0.0);
+}
+
+lazy_static! {
+    pub static ref STOP_AT_SYSCALL: bool =
+        lookup_bool_setting("Emulation", "stop_at_syscall", false);
+}
+
+fn lookup_address_setting(section: &str, item: &str) -> Option<u64> {
+    match lookup_string_setting(section, item, "".to_string()).as_str() {
+        "" => None,
+        addr => Some(parse_number(addr)),
+    }
+}
+
+lazy_static! {
+    /* bytes that mustn't appear anywhere in the payload, such as the NUL
+     * that would cut a strcpy short */
+    pub static ref BAD_BYTES: Vec<u8> =
+        parse_bytes(&lookup_string_setting("Constraints", "bad_bytes", "".to_string()));
+}
+
+lazy_static! {
+    /* the longest payload the exploit can deliver, in bytes, or 0 for no limit */
+    pub static ref MAX_PAYLOAD_SIZE: usize = lookup_usize_setting("Constraints", "max_payload_size", 0);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OnViolation {
+    Penalise,
+    Reject,
+}
+
+lazy_static! {
+    pub static ref ON_VIOLATION: OnViolation =
+        match lookup_string_setting("Constraints", "on_violation", "penalise".to_string()).as_str()
+        {
+            "reject" => OnViolation::Reject,
+            _ => OnViolation::Penalise,
+        };
+}
+
+lazy_static! {
+    /* the share of its fitness a chain loses for each bad byte, or each
+     * word past the size limit, when penalising */
+    pub static ref VIOLATION_PENALTY: f32 = lookup_f32_setting("Constraints", "violation_penalty", 0.25);
+}
+
+/* bad_bytes = 00 0a 0d */
+fn parse_bytes(list: &str) -> Vec<u8> {
+    split_list(list)
+        .map(|b| u8::from_str_radix(b.trim_start_matches("0x"), 16).expect("Bad byte in bad_bytes"))
+        .collect()
+}
+
+lazy_static! {
+    /* whether to relocate the image by a random slide for each case, as
+     * ASLR would, giving the chain the slide as its last input word */
+    pub static ref ASLR: bool = lookup_bool_setting("ASLR", "enabled", false);
+}
+
+lazy_static! {
+    /* whether gadget words are packed with the slide already added. If
+     * not, only the first gadget (where the exploit takes control) is
+     * slid for the chain, and it has to find its way to the rest with the
+     * slide it's handed as an input word. */
+    pub static ref SLIDE_GADGETS: bool = lookup_bool_setting("ASLR", "slide_gadgets", true);
+}
+
+lazy_static! {
+    /* how many slides each problem is posed with */
+    pub static ref ASLR_SLIDES: usize = lookup_usize_setting("ASLR", "slides", 4);
+}
+
+lazy_static! {
+    /* the largest slide, and the granularity of slides, in bytes */
+    pub static ref MAX_SLIDE: u64 =
+        parse_number(&lookup_string_setting("ASLR", "max_slide", "0x1000000".to_string()));
+    pub static ref SLIDE_ALIGN: u64 =
+        parse_number(&lookup_string_setting("ASLR", "slide_align", "0x1000".to_string()));
+}
+
+/// Reads a number that may be negative, like -0x10.
+pub fn parse_signed(s: &str) -> i64 {
+    let s = s.trim();
+    if s.starts_with('-') {
+        -(parse_number(&s[1..]) as i64)
+    } else {
+        parse_number(s) as i64
+    }
+}
+
+/// The setup for a given problem: the `[Emulation]` section, with any
+/// overrides for that problem applied.
+pub fn emulation_setup(input: &[u64]) -> &'static EmulationSetup {
+    let (problem, _) = split_slide(input);
+    EMULATION_OVERRIDES
+        .iter()
+        .find(|(i, _)| i.as_slice() == problem)
+        .map(|(_, setup)| setup)
+        .unwrap_or(&*EMULATION)
+}
+
+/// Splits a case into the problem's own input words and the slide the
+/// image is relocated by, which is 0 unless ASLR is enabled.
+pub fn split_slide(input: &[u64]) -> (&[u64], u64) {
+    match input.split_last() {
+        Some((slide, problem)) if *ASLR => (problem, *slide),
+        _ => (input, 0),
+    }
+}
+
+/* A minimal ELF header for a 32-bit x86 executable, with no program or
+ * section headers. Enough to exercise the architecture detection. */
+#[cfg(test)]
+const I386_ELF_HEADER: [u8; 52] = [
+    0x7f, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, /* e_ident */
+    2, 0, /* e_type: EXEC */
+    3, 0, /* e_machine: 386 */
+    1, 0, 0, 0, /* e_version */
+    0x00, 0x80, 0x04, 0x08, /* e_entry */
+    0, 0, 0, 0, /* e_phoff */
+    0, 0, 0, 0, /* e_shoff */
+    0, 0, 0, 0, /* e_flags */
+    52, 0, /* e_ehsize */
+    32, 0, /* e_phentsize */
+    0, 0, /* e_phnum */
+    40, 0, /* e_shentsize */
+    0, 0, /* e_shnum */
+    0, 0, /* e_shstrndx */
+];
+
+#[test]
+fn test_detect_i386() {
+    let arch = detect_arch(&I386_ELF_HEADER);
+    assert_eq!(arch, Arch::X86(Mode::Bits32));
+    assert_eq!(addr_width(arch), 4);
+    assert_eq!(addr_width(Arch::X86(Mode::Bits64)), 8);
+}
+
+#[test]
+fn test_pe_arch() {
+    assert_eq!(pe_arch(0x14c), Arch::X86(Mode::Bits32));
+    assert_eq!(pe_arch(0x8664), Arch::X86(Mode::Bits64));
+    assert_eq!(pe_arch(0x1c4), Arch::Arm(Mode::Thumb));
+    assert_eq!(pe_arch(0xaa64), Arch::Arm64(Mode::Le));
+}
+
+#[test]
+fn test_macho_arch() {
+    assert_eq!(macho_arch(CPU_TYPE_X86_64), Arch::X86(Mode::Bits64));
+    assert_eq!(macho_arch(CPU_TYPE_ARM64), Arch::Arm64(Mode::Le));
+}
+
+#[test]
+fn test_raw_arch() {
+    assert_eq!(raw_arch("mips", "big"), Arch::Mips(Mode::Be));
+    assert_eq!(raw_arch("thumb", "little"), Arch::Arm(Mode::Thumb));
+    assert_eq!(parse_number("0x08000000"), 0x08000000);
+    assert_eq!(parse_number("4096"), 4096);
+    assert_eq!(parse_signed("-0x10"), -16);
+}
+
+#[test]
+fn test_parse_library() {
+    assert_eq!(
+        parse_library("/lib/libc.so.6@0x7f0000000000*4"),
+        ("/lib/libc.so.6".to_string(), 0x7f0000000000, 4.0)
+    );
+    assert_eq!(
+        parse_library("libm.so@0x10000"),
+        ("libm.so".to_string(), 0x10000, 1.0)
+    );
+}
+
+#[test]
+fn test_emulation_settings() {
+    assert_eq!(
+        parse_registers("rdi=0x601000, RSI=7"),
+        vec![("rdi".to_string(), 0x601000), ("rsi".to_string(), 7)]
+    );
+    assert_eq!(
+        parse_preload("0x601000:2f62696e 0x10:00ff"),
+        vec![
+            (0x601000, vec![0x2f, 0x62, 0x69, 0x6e]),
+            (0x10, vec![0x00, 0xff])
+        ]
+    );
+}
+
+#[test]
+fn test_parse_ranges() {
+    assert_eq!(
+        parse_ranges("0x601000-0x602000, 16-32"),
+        vec![(0x601000, 0x602000), (16, 32)]
+    );
+}
+
+#[test]
+fn test_parse_bytes() {
+    assert_eq!(parse_bytes("00 0a, 0x0d"), vec![0x00, 0x0a, 0x0d]);
+    assert_eq!(parse_bytes(""), Vec::<u8>::new());
+}